@@ -0,0 +1,81 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::{env, fs, path::PathBuf, process::Command};
+
+// Builds a document made of a large table, it is rendered as a whole or row by row
+fn write_document() -> PathBuf {
+    let rows = (0..20_000)
+        .map(|i| {
+            format!(
+                "<tr class=\"row\"><td><a href=\"/item/{i}\">Item &amp; {i}</a></td><td>{i}</td><td>text   with\n  spaces</td></tr>",
+                i = i
+            )
+        })
+        .collect::<String>();
+    let path = env::temp_dir().join("rup-bench.html");
+    fs::write(
+        &path,
+        format!("<html><body><table>{}</table></body></html>", rows),
+    )
+    .unwrap();
+    path
+}
+
+// Extracts a record from every row of the table
+fn write_template() -> PathBuf {
+    let path = env::temp_dir().join("rup-bench.json");
+    fs::write(
+        &path,
+        r#"{"rows": {"selector": "tr", "each": {"name": "a", "url": "a | attr:href", "cells": "td | list"}}}"#,
+    )
+    .unwrap();
+    path
+}
+
+// Another build of rup given with RUP_BASELINE (e.g. one of the main branch) is run as well to compare them
+fn binaries() -> Vec<(&'static str, PathBuf)> {
+    let mut binaries = vec![("current", PathBuf::from(env!("CARGO_BIN_EXE_rup")))];
+    if let Some(path) = env::var_os("RUP_BASELINE") {
+        binaries.push(("baseline", PathBuf::from(path)));
+    }
+    binaries
+}
+
+fn render(c: &mut Criterion) {
+    let path = write_document();
+    let template_args = format!("--template {}", write_template().display());
+    let binaries = binaries();
+    let mut group = c.benchmark_group("render");
+    group.sample_size(10);
+    for args in [
+        "tr",
+        "table",
+        "-p table",
+        "-j tr",
+        "--ndjson tr",
+        "-t tr",
+        "--format text table",
+        // Every cell used to search the whole document for its base url
+        "--table=csv table",
+        // Every extracted value used to build its own settings
+        template_args.as_str(),
+    ] {
+        for (name, binary) in binaries.iter() {
+            group.bench_with_input(BenchmarkId::new(*name, args), args, |b, args| {
+                b.iter(|| {
+                    let output = Command::new(binary)
+                        .arg("-c")
+                        .arg("-f")
+                        .arg(&path)
+                        .args(args.split(' '))
+                        .output()
+                        .unwrap();
+                    assert!(output.status.success());
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, render);
+criterion_main!(benches);
@@ -0,0 +1,314 @@
+use encoding_rs::{Encoding, UTF_8};
+use std::{
+    fs,
+    io::{self, stdin, Read},
+    time::Duration,
+};
+
+// Represents a document read from stdin, a file or an http(s) url
+pub struct Document {
+    // The url the document was retrieved from once every redirect is followed, only defined for http(s) inputs
+    pub url: Option<String>,
+    pub content: Vec<u8>,
+}
+
+#[derive(Clone)]
+pub struct FetchSettings {
+    headers: Vec<(String, String)>,
+    user_agent: Option<String>,
+    cookies: Vec<String>,
+    timeout: Option<Duration>,
+    max_redirects: u32,
+}
+
+impl Default for FetchSettings {
+    fn default() -> Self {
+        FetchSettings {
+            headers: vec![],
+            user_agent: None,
+            cookies: vec![],
+            timeout: None,
+            max_redirects: 10,
+        }
+    }
+}
+
+pub struct FetchSettingsBuilder {
+    fetch_settings: FetchSettings,
+}
+
+impl FetchSettingsBuilder {
+    pub fn new() -> Self {
+        FetchSettingsBuilder {
+            fetch_settings: FetchSettings::default(),
+        }
+    }
+
+    pub fn add_header(&mut self, name: String, value: String) {
+        self.fetch_settings.headers.push((name, value));
+    }
+
+    pub fn set_user_agent(&mut self, user_agent: String) {
+        self.fetch_settings.user_agent = Some(user_agent);
+    }
+
+    // A cookie is a name=value pair, every cookie is sent in a single Cookie header
+    pub fn add_cookie(&mut self, cookie: String) {
+        self.fetch_settings.cookies.push(cookie);
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.fetch_settings.timeout = Some(timeout);
+    }
+
+    fn build(&mut self) -> FetchSettings {
+        self.fetch_settings.to_owned()
+    }
+}
+
+pub fn is_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+// Represents a document being read, used to process documents too big to be loaded at once
+pub struct Stream {
+    // The url the document is retrieved from once every redirect is followed, only defined for http(s) inputs
+    pub url: Option<String>,
+    // The charset declared by the http(s) response, utf-8 is assumed otherwise
+    pub encoding: &'static Encoding,
+    pub reader: Box<dyn Read>,
+}
+
+// Read a document from stdin when the source is "-", a file or an http(s) url
+pub fn fetch(settings_builder: FetchSettingsBuilder, source: &str) -> io::Result<Document> {
+    let mut stream = open(settings_builder, source)?;
+
+    let mut content: Vec<u8> = Vec::new();
+    stream.reader.read_to_end(&mut content)?;
+
+    // The parser expects utf-8, so any other charset declared in the response is converted
+    if stream.encoding != UTF_8 {
+        content = stream.encoding.decode(&content).0.into_owned().into_bytes();
+    }
+
+    Ok(Document {
+        url: stream.url,
+        content,
+    })
+}
+
+// Open a document from stdin when the source is "-", a file or an http(s) url without reading it
+pub fn open(mut settings_builder: FetchSettingsBuilder, source: &str) -> io::Result<Stream> {
+    let settings = settings_builder.build();
+
+    if is_url(source) {
+        return open_url(settings, source);
+    }
+
+    Ok(Stream {
+        url: None,
+        encoding: UTF_8,
+        reader: if source == "-" {
+            Box::new(stdin())
+        } else {
+            Box::new(fs::File::open(source)?)
+        },
+    })
+}
+
+fn open_url(settings: FetchSettings, url: &str) -> io::Result<Stream> {
+    let mut agent_builder = ureq::AgentBuilder::new().redirects(settings.max_redirects);
+    if let Some(timeout) = settings.timeout {
+        agent_builder = agent_builder.timeout(timeout);
+    }
+    if let Some(user_agent) = &settings.user_agent {
+        agent_builder = agent_builder.user_agent(user_agent);
+    }
+
+    let mut request = agent_builder.build().get(url);
+    for (name, value) in &settings.headers {
+        request = request.set(name, value);
+    }
+    if !settings.cookies.is_empty() {
+        request = request.set("Cookie", settings.cookies.join("; ").as_str());
+    }
+
+    let response = request
+        .call()
+        .map_err(|e| io::Error::other(e.to_string()))?;
+
+    Ok(Stream {
+        url: Some(response.get_url().to_string()),
+        encoding: Encoding::for_label(response.charset().as_bytes()).unwrap_or(UTF_8),
+        reader: response.into_reader(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use std::{
+        env,
+        io::{self, BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+        time::Duration,
+    };
+
+    use super::{fetch, Document, FetchSettingsBuilder};
+
+    // Starts a local http server standing in for a real website and returns its base url
+    fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                thread::spawn(move || {
+                    let mut request = vec![];
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                        request.push(line.trim().to_string());
+                    }
+
+                    let path = request[0].split(' ').nth(1).unwrap().to_string();
+                    let (head, body): (&str, Vec<u8>) = match path.as_str() {
+                        "/redirect" => ("302 Found\r\nLocation: /document", vec![]),
+                        "/document" => (
+                            "200 OK\r\nContent-Type: text/html",
+                            b"<p>document</p>".to_vec(),
+                        ),
+                        "/latin1" => (
+                            "200 OK\r\nContent-Type: text/html; charset=ISO-8859-1",
+                            b"<p>caf\xe9</p>".to_vec(),
+                        ),
+                        "/echo" => (
+                            "200 OK\r\nContent-Type: text/plain",
+                            request.join("\n").to_lowercase().into_bytes(),
+                        ),
+                        "/slow" => {
+                            thread::sleep(Duration::from_millis(500));
+                            ("200 OK\r\nContent-Type: text/html", b"<p>slow</p>".to_vec())
+                        }
+                        _ => ("404 Not Found", vec![]),
+                    };
+
+                    let _ = stream.write_all(
+                        format!(
+                            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                            head,
+                            body.len()
+                        )
+                        .as_bytes(),
+                    );
+                    let _ = stream.write_all(&body);
+                });
+            }
+        });
+
+        base_url
+    }
+
+    #[test]
+    fn fetch_documents() {
+        struct Scenario {
+            source: String,
+            settings: FetchSettingsBuilder,
+            test: Box<dyn Fn(io::Result<Document>, String)>,
+        }
+
+        let base_url = serve();
+
+        let scenarios: Vec<Scenario> = vec![
+            Scenario {
+                // Follow redirects and keep the final url
+                source: base_url.to_owned() + "/redirect",
+                settings: FetchSettingsBuilder::new(),
+                test: Box::new(|actual, base_url| {
+                    let document = actual.unwrap();
+                    assert_eq!(document.url, Some(base_url + "/document"));
+                    assert_eq!(
+                        String::from_utf8(document.content).unwrap(),
+                        "<p>document</p>"
+                    );
+                }),
+            },
+            Scenario {
+                // Convert the response charset to utf-8
+                source: base_url.to_owned() + "/latin1",
+                settings: FetchSettingsBuilder::new(),
+                test: Box::new(|actual, _| {
+                    assert_eq!(
+                        String::from_utf8(actual.unwrap().content).unwrap(),
+                        "<p>café</p>"
+                    );
+                }),
+            },
+            Scenario {
+                // Send headers, user-agent and cookies
+                source: base_url.to_owned() + "/echo",
+                settings: {
+                    let mut s = FetchSettingsBuilder::new();
+                    s.add_header("X-Token".to_string(), "abcd".to_string());
+                    s.set_user_agent("rup-test".to_string());
+                    s.add_cookie("session=1234".to_string());
+                    s.add_cookie("lang=fr".to_string());
+                    s
+                },
+                test: Box::new(|actual, _| {
+                    let request = String::from_utf8(actual.unwrap().content).unwrap();
+                    let lines = request.lines().collect::<Vec<&str>>();
+                    assert!(lines.contains(&"x-token: abcd"));
+                    assert!(lines.contains(&"user-agent: rup-test"));
+                    assert!(lines.contains(&"cookie: session=1234; lang=fr"));
+                }),
+            },
+            Scenario {
+                // Give up when the server is too slow to answer
+                source: base_url.to_owned() + "/slow",
+                settings: {
+                    let mut s = FetchSettingsBuilder::new();
+                    s.set_timeout(Duration::from_millis(100));
+                    s
+                },
+                test: Box::new(|actual, _| {
+                    assert!(actual.is_err());
+                }),
+            },
+            Scenario {
+                // Fail on error status code
+                source: base_url.to_owned() + "/missing",
+                settings: FetchSettingsBuilder::new(),
+                test: Box::new(|actual, base_url| {
+                    assert_eq!(
+                        actual.err().unwrap().to_string(),
+                        base_url + "/missing: status code 404"
+                    );
+                }),
+            },
+            Scenario {
+                // Read a file
+                source: env::var("CARGO_MANIFEST_DIR").unwrap() + "/src/fetcher/document.html",
+                settings: FetchSettingsBuilder::new(),
+                test: Box::new(|actual, _| {
+                    let document = actual.unwrap();
+                    assert_eq!(document.url, None);
+                    assert_eq!(
+                        String::from_utf8(document.content).unwrap(),
+                        "<p>document</p>\n"
+                    );
+                }),
+            },
+        ];
+
+        for s in scenarios {
+            (s.test)(fetch(s.settings, s.source.as_str()), base_url.to_owned());
+        }
+    }
+}
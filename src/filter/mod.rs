@@ -1,19 +1,101 @@
 use std::{cell::RefCell, rc::Rc};
 
 use crate::parser::{CssCombinator, CssSelector, CssSelectorAttribute, PseudoClass};
+use crate::tree;
 
 use super::parser;
 use html5ever::tendril::{stream::TendrilSink, ByteTendril};
-use html5ever::{parse_document, Attribute};
+use html5ever::{parse_document, parse_fragment, Attribute, LocalName, QualName};
+use markup5ever::{namespace_url, ns};
 use markup5ever_rcdom::{Handle, Node, NodeData, RcDom};
 use parser::AttributeSign;
+use xml5ever::driver::{parse_document as parse_xml_document, XmlParseOpts};
 
-// Filters html nodes matching the given css expression
-pub fn filter(content: Vec<u8>, selectors: &Vec<parser::CssSelector>) -> Vec<Rc<Node>> {
-    let root_node = parse_document(RcDom::default(), Default::default())
+// Parses an html document, the returned document must outlive the filtered nodes
+// as dropping it empties every node of the tree
+pub fn parse_html(content: &[u8]) -> Handle {
+    parse_document(RcDom::default(), Default::default())
+        .from_utf8()
+        .one(ByteTendril::from(content))
+        .document
+}
+
+// Parses an xml document (e.g. rss, atom, sitemap, xhtml), names are kept case-sensitive
+// and namespaced, the returned document must outlive the filtered nodes as well
+pub fn parse_xml(content: &[u8]) -> Handle {
+    parse_xml_document(RcDom::default(), XmlParseOpts::default())
         .from_utf8()
-        .one(ByteTendril::from(content.as_slice()));
-    filter_matching_nodes(root_node.document.to_owned(), &selectors, 0, 0, 0)
+        .one(ByteTendril::from(content))
+        .document
+}
+
+// The elements of the html standard, obsolete ones included, a snippet can be parsed in
+const HTML_ELEMENTS: &str = "\
+     a abbr acronym address applet area article aside audio b base basefont bdi bdo bgsound big blink \
+     blockquote body br button canvas caption center cite code col colgroup data datalist dd del \
+     details dfn dialog dir div dl dt em embed fieldset figcaption figure font footer form frame \
+     frameset h1 h2 h3 h4 h5 h6 head header hgroup hr html i iframe image img input ins isindex kbd \
+     keygen label legend li link listing main map mark marquee menu menuitem meta meter multicol nav \
+     nextid nobr noembed noframes noscript object ol optgroup option output p param picture plaintext \
+     pre progress q rb rp rt rtc ruby s samp script search section select slot small source spacer \
+     span strike strong style sub summary sup table tbody td template textarea tfoot th thead time \
+     title tr track tt u ul var video wbr xmp";
+
+// A snippet is parsed as the content of an html element, whatever the case of its name
+pub fn check_fragment_context(context_element: &str) -> Result<(), String> {
+    let name = context_element.to_ascii_lowercase();
+    if HTML_ELEMENTS
+        .split_ascii_whitespace()
+        .any(|element| element == name)
+    {
+        Ok(())
+    } else {
+        Err(format!("{} is not an html element", context_element))
+    }
+}
+
+// Parses an html snippet as if it was the content of the context element, so the parser
+// doesn't wrap it in html/head/body nor drop elements misplaced outside of their parent (e.g. a tr out of a table).
+// The nodes of the snippet are moved at the root of the returned document
+pub fn parse_html_fragment(content: &[u8], context_element: &str) -> Handle {
+    let document = parse_fragment(
+        RcDom::default(),
+        Default::default(),
+        QualName::new(
+            None,
+            ns!(html),
+            LocalName::from(context_element.to_ascii_lowercase()),
+        ),
+        vec![],
+    )
+    .from_utf8()
+    .one(ByteTendril::from(content))
+    .document;
+
+    // The parser puts the snippet in an html element standing for the context element
+    let root = document.children.take().into_iter().next();
+    if let Some(root) = root {
+        for child in root.children.take() {
+            tree::append(&document, child);
+        }
+    }
+
+    document
+}
+
+// Filters html nodes matching the given css expression
+pub fn filter(document: &Handle, selectors: &Vec<parser::CssSelector>) -> Vec<Rc<Node>> {
+    filter_matching_nodes(document.to_owned(), selectors, 0, 0, 0, false)
+}
+
+// Like filter but the descendants of a matched node are searched as well, a node comes before its descendants
+pub fn filter_nested(document: &Handle, selectors: &Vec<parser::CssSelector>) -> Vec<Rc<Node>> {
+    filter_matching_nodes(document.to_owned(), selectors, 0, 0, 0, true)
+}
+
+// Like filter but the node itself is never matched, only its descendants are
+pub fn filter_descendants(node: &Handle, selectors: &Vec<parser::CssSelector>) -> Vec<Rc<Node>> {
+    explore_children_nodes(node.to_owned(), selectors, 0, false)
 }
 
 // Traverses the DOM recursively to filter matching nodes
@@ -23,9 +105,10 @@ fn filter_matching_nodes(
     index: usize,
     position: usize,
     length: usize,
+    is_nested: bool,
 ) -> Vec<Rc<Node>> {
     match node.data {
-        NodeData::Document => explore_children_nodes(node, selectors, index),
+        NodeData::Document => explore_children_nodes(node, selectors, index, is_nested),
         NodeData::Element {
             ref name,
             ref attrs,
@@ -43,10 +126,15 @@ fn filter_matching_nodes(
 
             if !is_matching_node && selector.combinator == CssCombinator::DirectChild {
                 vec![]
+            } else if next_index == selectors.len() && is_matching_node && is_nested {
+                // The descendants are searched as if the node didn't match the last selector
+                let mut nodes = vec![node.to_owned()];
+                nodes.extend(explore_children_nodes(node, selectors, index, is_nested));
+                nodes
             } else if next_index == selectors.len() && is_matching_node {
                 vec![node.to_owned()]
             } else {
-                explore_children_nodes(node, selectors, next_index)
+                explore_children_nodes(node, selectors, next_index, is_nested)
             }
         }
         _ => vec![],
@@ -57,8 +145,9 @@ fn explore_children_nodes(
     node: Rc<Node>,
     selectors: &Vec<CssSelector>,
     index: usize,
+    is_nested: bool,
 ) -> Vec<Rc<Node>> {
-    let ns = node.children.take();
+    let ns = node.children.borrow();
     let length = ns
         .iter()
         .filter(|n| matches!(n.data, NodeData::Element { .. }))
@@ -68,7 +157,9 @@ fn explore_children_nodes(
     ns.iter()
         // An internal counter is kept to avoid counting nodes differents than Element
         .fold((vec![], 0), |mut acc, n| {
-            for nc in filter_matching_nodes(n.to_owned(), selectors, index, acc.1, length) {
+            for nc in
+                filter_matching_nodes(n.to_owned(), selectors, index, acc.1, length, is_nested)
+            {
                 acc.0.push(nc);
             }
             (
@@ -82,14 +173,15 @@ fn explore_children_nodes(
         .0
 }
 
-fn is_matching_selector_name(selector: &CssSelector, element_name: impl AsRef<str>) -> bool {
+// The universal selector * matches any element
+pub fn is_matching_selector_name(selector: &CssSelector, element_name: impl AsRef<str>) -> bool {
     selector
         .to_owned()
         .name
-        .map_or_else(|| true, |v| v.as_str() == element_name.as_ref())
+        .map_or_else(|| true, |v| v == "*" || v.as_str() == element_name.as_ref())
 }
 
-fn is_matching_selector_attributes(
+pub fn is_matching_selector_attributes(
     selector: &CssSelector,
     attrs: &RefCell<Vec<Attribute>>,
     position: usize,
@@ -124,44 +216,41 @@ fn is_matching_selector_attributes(
                             vec![attr.value.to_string()]
                         };
 
-                        vec![attr.name.local.as_ref()]
+                        [attr.name.local.as_ref()]
                             .repeat(v.len())
                             .into_iter()
                             .zip(v)
                             .collect::<Vec<_>>()
                     })
-                    .into_iter()
                     .filter(|v| match &c {
-                        CssSelectorAttribute::ID(id) => (*v).0 == "id" && (*v).1 == id.to_owned(),
-                        CssSelectorAttribute::Class(class) => {
-                            (*v).0 == "class" && (*v).1 == class.to_owned()
-                        }
+                        CssSelectorAttribute::ID(id) => v.0 == "id" && v.1 == *id,
+                        CssSelectorAttribute::Class(class) => v.0 == "class" && v.1 == *class,
                         CssSelectorAttribute::Attribute(attr, AttributeSign::Empty, None) => {
-                            (*v).0 == *attr
+                            v.0 == *attr
                         }
                         CssSelectorAttribute::Attribute(attr, AttributeSign::Equal, Some(val)) => {
-                            (*v).0 == *attr && (*v).1 == *val
+                            v.0 == *attr && v.1 == *val
                         }
                         CssSelectorAttribute::Attribute(
                             attr,
                             AttributeSign::Contain,
                             Some(val),
-                        ) => (*v).0 == *attr && (*v).1.contains(val.as_str()),
+                        ) => v.0 == *attr && v.1.contains(val.as_str()),
                         CssSelectorAttribute::Attribute(
                             attr,
                             AttributeSign::BeginWith,
                             Some(val),
-                        ) => (*v).0 == *attr && (*v).1.starts_with(val.as_str()),
+                        ) => v.0 == *attr && v.1.starts_with(val.as_str()),
                         CssSelectorAttribute::Attribute(
                             attr,
                             AttributeSign::EndWith,
                             Some(val),
-                        ) => (*v).0 == *attr && (*v).1.ends_with(val.as_str()),
+                        ) => v.0 == *attr && v.1.ends_with(val.as_str()),
                         CssSelectorAttribute::Attribute(
                             attr,
                             AttributeSign::ContainWord,
                             Some(val),
-                        ) => (*v).0 == *attr && (*v).1.split(' ').any(|w| w == val.as_str()),
+                        ) => v.0 == *attr && v.1.split(' ').any(|w| w == val.as_str()),
                         _ => false,
                     })
                     .collect::<Vec<_>>()
@@ -761,12 +850,15 @@ mod tests {
             ),
         ];
 
+        let whitespaces = regex::Regex::new(">\\s*<").unwrap();
+
         for (css_selectors, filename, expected_html, matching_node_count) in scenarios {
             let content = fs::read_to_string(
                 env::var("CARGO_MANIFEST_DIR").unwrap() + "/src/filter/" + filename,
             )
             .unwrap();
-            let nodes = filter(content.into_bytes(), &css_selectors);
+            let document = parse_html(content.as_bytes());
+            let nodes = filter(&document, &css_selectors);
 
             assert_eq!(nodes.len(), matching_node_count);
 
@@ -786,7 +878,7 @@ mod tests {
             }
 
             debug_assert_eq!(
-                regex::Regex::new(">\\s*<").unwrap().replace_all(
+                whitespaces.replace_all(
                     String::from_utf8(bg.to_bytes().into_iter().collect())
                         .unwrap()
                         .as_ref(),
@@ -796,4 +888,115 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn filter_fragments() {
+        let scenarios = vec![
+            (
+                // Table rows parsed in a template context are kept
+                vec![
+                    CssSelector {
+                        name: Some("tr".to_string()),
+                        attributes: vec![],
+                        combinator: CssCombinator::Descendant,
+                    },
+                    CssSelector {
+                        name: Some("td".to_string()),
+                        attributes: vec![CssSelectorAttribute::PseudoClass(
+                            parser::PseudoClass::FirstChild,
+                        )],
+                        combinator: CssCombinator::DirectChild,
+                    },
+                ],
+                "template",
+                "table_rows_fragment.html",
+                r#"<td>First</td><td>Second</td>"#,
+                2,
+            ),
+            (
+                // Nodes of the fragment are at the root of the document
+                vec![CssSelector {
+                    name: Some("tr".to_string()),
+                    attributes: vec![CssSelectorAttribute::PseudoClass(
+                        parser::PseudoClass::LastChild,
+                    )],
+                    combinator: CssCombinator::DirectChild,
+                }],
+                "tbody",
+                "table_rows_fragment.html",
+                r#"<tr><td>Second</td><td>2</td></tr>"#,
+                1,
+            ),
+            (
+                // The name of the context element is case-insensitive
+                vec![CssSelector {
+                    name: Some("tr".to_string()),
+                    attributes: vec![CssSelectorAttribute::PseudoClass(
+                        parser::PseudoClass::LastChild,
+                    )],
+                    combinator: CssCombinator::DirectChild,
+                }],
+                "TBody",
+                "table_rows_fragment.html",
+                r#"<tr><td>Second</td><td>2</td></tr>"#,
+                1,
+            ),
+            (
+                // Table rows are dropped when parsed in a body context
+                vec![CssSelector {
+                    name: Some("tr".to_string()),
+                    attributes: vec![],
+                    combinator: CssCombinator::Descendant,
+                }],
+                "body",
+                "table_rows_fragment.html",
+                r#""#,
+                0,
+            ),
+        ];
+
+        let whitespaces = regex::Regex::new(">\\s*<").unwrap();
+
+        for (css_selectors, context_element, filename, expected_html, matching_node_count) in
+            scenarios
+        {
+            let content = fs::read_to_string(
+                env::var("CARGO_MANIFEST_DIR").unwrap() + "/src/filter/" + filename,
+            )
+            .unwrap();
+            let document = parse_html_fragment(content.as_bytes(), context_element);
+            let nodes = filter(&document, &css_selectors);
+
+            assert_eq!(nodes.len(), matching_node_count);
+
+            let mut bg = bytebuffer::ByteBuffer::new();
+
+            for node in nodes {
+                let t = SerializableHandle::from(node.to_owned());
+                let mut b = bytebuffer::ByteBuffer::new();
+                let traversal_scope = SerializeOpts {
+                    scripting_enabled: true,
+                    traversal_scope: TraversalScope::IncludeNode,
+                    create_missing_parent: false,
+                };
+
+                serialize(&mut b, &t, traversal_scope).unwrap();
+                bg.write_bytes(b.to_bytes().as_ref());
+            }
+
+            assert_eq!(
+                whitespaces.replace_all(
+                    String::from_utf8(bg.to_bytes().into_iter().collect())
+                        .unwrap()
+                        .trim(),
+                    "><"
+                ),
+                expected_html
+            );
+        }
+
+        assert!(check_fragment_context("TR").is_ok());
+        assert!(check_fragment_context("h7").is_err());
+        assert!(check_fragment_context("svg").is_err());
+    }
 }
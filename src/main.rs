@@ -1,56 +1,634 @@
-use clap::{App, Arg};
-use std::io::{stdin, Read};
+use clap::{App, Arg, ArgGroup, ArgMatches, ErrorKind};
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    io::{self, IsTerminal, Write},
+    process,
+    time::Duration,
+};
+use url::Url;
 
-use crate::renderer::SerializeSettingsBuilder;
+use markup5ever_rcdom::Handle;
 
+use crate::fetcher::{Document, FetchSettingsBuilder};
+use crate::output::{AtomicFile, Output};
+use crate::renderer::{AttributesFormat, JsonFormat, SerializeSettingsBuilder, TextMode};
+use crate::sanitize::Policy;
+use crate::table::TableFormat;
+use crate::transform::{Operation, Transformation};
+
+mod fetcher;
 mod filter;
+mod output;
 mod parser;
 mod renderer;
+mod sanitize;
+mod stream;
+mod table;
+mod template;
+mod transform;
+mod tree;
 
 fn main() {
     let matches = App::new("rup")
         .version("0.0.1")
         .author("Anthony Hamon <hamon.anth@gmail.com>")
         .about("jq for html")
-        .arg(Arg::new("no-color").long("no-color").short('c').about("Disable colors, NO_COLOR environment variable could be used as well see https://no-color.org/"))
+        .arg(Arg::new("no-color").long("no-color").short('c').about("Disable colors, same as --color=never"))
+        .arg(Arg::new("color").long("color").takes_value(true).require_equals(true).possible_values(&["auto", "always", "never"]).default_value("auto").value_name("WHEN").about("Color the markup and the JSON documents: auto colors the output when it is a terminal and the NO_COLOR environment variable is not defined (see https://no-color.org/), CLICOLOR_FORCE forces the colors as well. The colors are defined in the RUP_COLORS environment variable as a list of STYLE=COLOR separated with colons, e.g. RUP_COLORS='tag=blue:value=208:key=#5f87ff'. Styles are tag, attribute, value, comment, key, string, number, boolean, null and punctuation, a color is a name (black, red, green, yellow, blue, magenta, cyan, white and their bright_ variants), a number of the 256-color palette, a #rrggbb truecolor or none"))
         .arg(Arg::new("keep-text-only").long("keep-text-only").short('t').about("Extract the text from every end matched node, if a node has children, the text of every node is extracted and concatened with a space as separator"))
-        .arg(Arg::new("keep-attributes-values").long("keep-attributes-values").short('a').conflicts_with("keep-text-only").takes_value(true) .multiple_values(true).about("Extract provided node attributes from every end matched node using the attribute key, if several attributes are provided or if an attribute is found more than once for a given node, values are extracted and concatened with a space as separator"))
+        .arg(Arg::new("keep-attributes-values").long("keep-attributes-values").short('a').conflicts_with("keep-text-only").takes_value(true) .multiple_values(true).about("Extract provided node attributes from every end matched node using the attribute key, if several attributes are provided or if an attribute is found more than once for a given node, values are extracted and concatened with a space as separator. An attribute could be a glob where * matches any sequence of characters, e.g. '*' for every attribute or 'data-*'"))
+        .arg(Arg::new("attributes-format").long("attributes-format").takes_value(true).require_equals(true).possible_values(&["json", "tsv"]).value_name("FORMAT").requires("keep-attributes-values").conflicts_with("format").conflicts_with("stream").about("Render the attributes extracted with --keep-attributes-values as a record per matched node having a column per attribute: json writes every record as a JSON object on its own line where a missing attribute is null, tsv writes a header row followed by a row of tab-separated values per node where a missing attribute is empty. A glob stands for the matching attributes found on the matched nodes"))
+        .arg(Arg::new("text-mode").long("text-mode").takes_value(true).require_equals(true).possible_values(&["all", "own", "inner"]).value_name("MODE").requires("text-extraction").about("The texts extracted with --keep-text-only, --format-string or --template: all (by default) extracts the texts of the node and of its descendants except the content of script and style elements, own extracts the texts which are direct children of the node and inner extracts the text like the innerText property of browsers, content hidden by a template or noscript element, a hidden attribute or a display:none inline style is left out and block elements start on a new line"))
+        .arg(Arg::new("text-nodes").long("text-nodes").requires("text-extraction").about("Extract every text node on its own line instead of joining the texts with a space"))
         .arg(Arg::new("json").long("json").short('j').conflicts_with("keep-text-only").conflicts_with("keep-attributes-values").about("Render html nodes as a JSON document. When a node property does not contain any data it is set to null. A type property separate comment, regular markup, doctype and processor instructions"))
+        .arg(Arg::new("pretty").long("pretty").short('p').conflicts_with("keep-text-only").conflicts_with("keep-attributes-values").conflicts_with("json").about("Render every block-level element (div, p, li, tr, ...) on its own line and indent its content with 2 spaces, the content of pre, textarea, script and style elements is kept intact"))
+        .arg(Arg::new("indent").long("indent").takes_value(true).value_name("N").validator(|v| v.parse::<usize>()).conflicts_with("keep-text-only").conflicts_with("keep-attributes-values").conflicts_with("json").about("Same as --pretty but indent with N spaces"))
+        .arg(Arg::new("raw").long("raw").short('r').conflicts_with("keep-text-only").conflicts_with("keep-attributes-values").conflicts_with("json").conflicts_with("pretty").conflicts_with("indent").conflicts_with("base-url").about("Render matched nodes as found in the document: whitespaces of texts, comments and attribute values are kept and urls are not resolved, only the normalization of the html parser (e.g. quotes, implied tags, entities) remains"))
+        .arg(Arg::new("minify").long("minify").short('m').conflicts_with_all(&["keep-text-only", "keep-attributes-values", "json", "ndjson", "format", "table", "template", "format-string", "pretty", "indent", "raw", "xml"]).about("Render matched nodes as small as possible while browsers still parse them the same: comments are dropped, whitespaces are collapsed except in pre, textarea, script and style elements and dropped around block-level elements, optional end tags (e.g. </li>, </p>, </td>) and the quotes of attribute values are omitted when the html syntax allows it and boolean attributes are reduced to their name"))
+        .arg(Arg::new("keep-comments").long("keep-comments").requires("minify").about("Keep the comments with --minify"))
+        .arg(Arg::new("ndjson").long("ndjson").conflicts_with("keep-text-only").conflicts_with("keep-attributes-values").conflicts_with("json").conflicts_with("pretty").conflicts_with("indent").conflicts_with("raw").about("Render every matched node as a JSON object written on its own line (newline-delimited JSON), the objects have the same properties as with --json (--json-format applies as well)"))
+        .arg(Arg::new("with-selector").long("with-selector").requires("ndjson").about("Add to every JSON object a selector property holding the css selector that matched the node"))
+        .arg(Arg::new("with-source").long("with-source").requires("ndjson").about("Add to every JSON object a source property holding the file or the url the node was read from, - for stdin"))
+        .arg(Arg::new("json-format").long("json-format").takes_value(true).require_equals(true).possible_values(&["rup", "pup", "lossless"]).default_value("rup").value_name("FORMAT").about("The shape of the JSON documents rendered with --json or --ndjson: rup nodes have a type, a text, attributes and children properties, pup nodes have a tag property, their attributes as properties and the texts of their children concatenated like pup does, lossless is like rup but texts are kept as is in children nodes of type text, in document order, so the html can be rendered back"))
+        .arg(Arg::new("format").long("format").takes_value(true).possible_values(&["yaml", "toml", "markdown", "text"]).value_name("FORMAT").conflicts_with("json").conflicts_with("ndjson").conflicts_with("pretty").conflicts_with("indent").conflicts_with("raw").about("Render html nodes as a yaml or a toml document having the same properties as with --json (--json-format applies as well), with --keep-text-only or --keep-attributes-values the extracted values are rendered as a list of strings. A toml document being a table, the list is the value of a nodes, texts or attributes key and null values are left out. markdown converts every matched node to a CommonMark document using the GitHub flavored tables and strikethrough: headings, emphasis, links and images (urls are resolved like with --base-url), lists, code blocks, blockquotes and tables are converted, other elements are replaced with their content. text lays out every matched node as readable plain text like lynx -dump does: block elements start on a new line, lists get bullets or numbers, tables are aligned in columns, links are numbered and listed as references at the end and paragraphs are wrapped to --width columns"))
+        .arg(Arg::new("width").long("width").takes_value(true).value_name("COLUMNS").validator(renderer::parse_width).requires("format").about("The number of columns paragraphs are wrapped to with --format text, 80 by default, a word longer than the width is not split"))
+        .arg(Arg::new("table").long("table").takes_value(true).possible_values(&["csv", "tsv", "json"]).value_name("FORMAT").conflicts_with_all(&["keep-text-only", "keep-attributes-values", "json", "ndjson", "format", "pretty", "indent", "raw", "stream"]).about("Extract the table elements matched by the selectors as csv, tsv or a JSON array of records keyed by the header cells. Cells spanning several rows or columns are copied in every row and column they cover, header rows are the rows of thead or the first rows made of th cells and their texts are extracted like with --keep-text-only"))
+        .arg(Arg::new("template").long("template").takes_value(true).value_name("FILE").conflicts_with_all(&["selectors", "keep-text-only", "keep-attributes-values", "json", "ndjson", "format", "pretty", "indent", "raw", "stream", "table"]).about(r#"Extract a JSON record from every document using a JSON template mapping field names to "SELECTOR | MODE" where modes are text (by default), attr:NAME, html, count and list to extract every matched node instead of the first one, e.g. {"title": "h1", "links": "a | attr:href | list"}. A field defined as {"selector": SELECTOR, "each": TEMPLATE} extracts a record from every matched node, the selectors of the nested template being scoped to the node"#))
+        .arg(Arg::new("format-string").long("format-string").takes_value(true).value_name("FORMAT").validator(|v| renderer::parse_format_string(v).map(|_| ())).conflicts_with_all(&["keep-text-only", "keep-attributes-values", "json", "ndjson", "format", "pretty", "indent", "table", "template"]).about(r#"Print every matched node on its own line using a format string, e.g. '{attr:href}\t{text}'. Placeholders are {name} for the tag name, {attr:NAME} for the value of an attribute, {text} for the text extracted like with --keep-text-only, {inner_html} and {outer_html} for the markup of the children and of the node, {index} for the position of the node among the matched nodes starting from 1, {source} for the file or the url the node was read from and {selector} for the css selector that matched the node. \t, \n and \\ are replaced with a tab, a new line and a backslash, {{ and }} with a brace"#))
+        .arg(Arg::new("delete").long("delete").takes_value(true).multiple_occurrences(true).value_name("SELECTORS").conflicts_with("stream").about("Remove the nodes matching the css selectors, several selectors are separated with a comma (e.g. 'script, .ad'). Once transformed, the whole document is rendered as found unless selectors are given. Transformations are applied in the order they are provided"))
+        .arg(Arg::new("unwrap").long("unwrap").takes_value(true).multiple_occurrences(true).value_name("SELECTORS").conflicts_with("stream").about("Replace the elements matching the css selectors with their children"))
+        .arg(Arg::new("set-attr").long("set-attr").takes_value(true).number_of_values(2).multiple_occurrences(true).value_names(&["SELECTORS", "NAME=VALUE"]).conflicts_with("stream").about("Set an attribute of the elements matching the css selectors, an attribute given without value is set empty (e.g. --set-attr img loading=lazy)"))
+        .arg(Arg::new("remove-attr").long("remove-attr").takes_value(true).number_of_values(2).multiple_occurrences(true).value_names(&["SELECTORS", "NAME"]).conflicts_with("stream").about("Remove an attribute of the elements matching the css selectors, the name can be a glob where * matches any sequence of characters (e.g. --remove-attr '*' 'on*')"))
+        .arg(Arg::new("replace-text").long("replace-text").takes_value(true).number_of_values(2).multiple_occurrences(true).value_names(&["SELECTORS", "TEXT"]).conflicts_with("stream").about("Replace the content of the elements matching the css selectors with a text"))
+        .arg(Arg::new("wrap").long("wrap").takes_value(true).number_of_values(2).multiple_occurrences(true).value_names(&["SELECTORS", "ELEMENT"]).conflicts_with("stream").about(r#"Wrap the elements matching the css selectors in a new element given as a start tag or a name (e.g. --wrap img '<figure class="image">')"#))
+        .arg(Arg::new("sanitize").long("sanitize").takes_value(true).value_name("POLICY").conflicts_with("stream").about(r#"Remove the elements and attributes not allowed by a toml policy file and report on stderr what was removed. Elements are kept when they match one of the elements selectors, the ones matching removed_elements (script, style, template, iframe, object and embed by default) are removed with their content and the others are replaced with their content, or removed in the head. The html, head and body elements of a document are always kept. Attributes are kept when one of the attributes selectors matches their element and they are listed by name or glob. Event handler and srcdoc attributes, noscript elements and urls whose scheme isn't listed in url_schemes (http, https and mailto by default), including the urls of styles and refresh directives, are always removed, comments are removed unless comments = true. The document is sanitized after the other transformations, e.g.
+    elements = ["p", "a", "img", "div.note"]
+    url_schemes = ["https"]
+    [attributes]
+    a = ["href", "title"]
+    "*" = ["class", "data-*"]"#))
+        .arg(Arg::new("output").long("output").short('o').takes_value(true).value_name("FILE").about("Write the output to a file instead of stdout, the file is replaced once everything is written so it is never left half written"))
+        .arg(Arg::new("in-place").long("in-place").short('i').takes_value(true).min_values(0).require_equals(true).value_name("SUFFIX").conflicts_with_all(&["output", "keep-text-only", "keep-attributes-values", "json", "ndjson", "format", "table", "template", "format-string", "stream", "from-json", "selectors"]).about("Replace every input file with the whole document transformed by --delete, --unwrap, --set-attr, --remove-attr, --replace-text, --wrap or --sanitize instead of writing to stdout, one of them is required and selectors can't be given. When a suffix is given (e.g. -i.bak), the file is first copied to a file named with the suffix appended. A file is replaced at once when it is rendered, so it is never left half written"))
+        .arg(Arg::new("input").long("input").short('f').takes_value(true).multiple_occurrences(true).value_name("FILE|URL").about("Read the html document from a file or an http(s) url instead of stdin, this option could be provided several times to process several documents"))
+        .arg(Arg::new("header").long("header").short('H').takes_value(true).multiple_occurrences(true).value_name("NAME: VALUE").validator(|v| v.split_once(':').map(|_| ()).ok_or("a header must be defined as NAME: VALUE")).about("Add a header to every http(s) request, this option could be provided several times"))
+        .arg(Arg::new("user-agent").long("user-agent").short('A').takes_value(true).about("Define the user agent of every http(s) request"))
+        .arg(Arg::new("cookie").long("cookie").short('b').takes_value(true).multiple_occurrences(true).value_name("NAME=VALUE").about("Add a cookie to every http(s) request, this option could be provided several times"))
+        .arg(Arg::new("timeout").long("timeout").takes_value(true).value_name("SECONDS").validator(|v| v.parse::<u64>()).about("Abort an http(s) request taking more than the given number of seconds"))
+        .arg(Arg::new("verbose").long("verbose").short('v').about("Print on stderr the final url of every http(s) input once redirects are followed"))
+        .arg(Arg::new("base-url").long("base-url").takes_value(true).value_name("URL").validator(Url::parse).about("Resolve relative urls found in url attributes (href, src, srcset, action, ...) against this url, a <base href> element found in the document takes precedence. The url of an http(s) input is used when this option is not provided"))
+        .arg(Arg::new("fragment").long("fragment").takes_value(true).min_values(0).require_equals(true).value_name("CONTEXT-ELEMENT").validator(filter::check_fragment_context).about("Parse the input as an html snippet (e.g. <tr><td>1</td></tr>) instead of a whole document, so it isn't wrapped in html/head/body and misplaced elements are kept. The snippet is parsed as the content of the context element, an html element whose name is case-insensitive, template is used by default as it accepts any element"))
+        .arg(Arg::new("xml").long("xml").short('x').conflicts_with("fragment").about("Parse the input as an xml document (e.g. rss, atom, sitemap, xhtml) and render well-formed xml, names are case-sensitive and namespace prefixes are kept"))
+        .arg(Arg::new("stream").long("stream").short('s').conflicts_with("fragment").conflicts_with("xml").about("Filter the document while it is read and write every matched node as soon as its end tag is found, memory stays bounded whatever the size of the document. Only the element, id, class, attribute, :first-child and :nth-child selectors and the descendant and direct child combinators are supported in a single selector chain. Like without --stream, the descendants of a matched node aren't searched. Matched nodes are written in document order and elements implied by the html parser (e.g. tbody) are not added"))
+        .arg(Arg::new("from-json").long("from-json").conflicts_with("xml").conflicts_with("fragment").conflicts_with("stream").about("Read nodes rendered with --json or --ndjson instead of an html document and render them as html, every node is rendered when no selector is provided. Use --json-format=lossless to keep texts at their place"))
         .arg(Arg::new("selectors").multiple_values(true).about(r#"Css selectors, it is possible to provide several selectors by separating them with a space, pay attention to the fact that "div" "span" is different than "div span", the first one select all div nodes and all span nodes the second one select span nodes children of a div node"#))
-        .get_matches();
+        // The options of the text extraction are meaningless without something extracting texts
+        .group(ArgGroup::new("text-extraction").args(&["keep-text-only", "format-string", "template"]).multiple(true))
+        .get_matches_from(glue_in_place_suffix(env::args_os()));
 
-    let mut buffer: Vec<u8> = Vec::new();
-    stdin().read_to_end(&mut buffer).unwrap();
+    let inputs = matches
+        .values_of("input")
+        .map_or_else(|| vec!["-"], |inputs| inputs.collect());
+    let selector_chains = matches
+        .values_of("selectors")
+        .map_or_else(Vec::new, |selector_chains| selector_chains.collect());
 
-    match matches.values_of("selectors") {
-        Some(selector_chains) => {
-            selector_chains.for_each(|selector_chain| {
-                let mut settings_builder = SerializeSettingsBuilder::new();
-                if !matches.is_present("no-color") {
-                    settings_builder.enable_color();
-                }
-                if matches.is_present("keep-text-only") {
-                    settings_builder.should_render_text_only();
-                }
-                if matches.is_present("json") {
-                    settings_builder.render_json();
+    let operations = match build_operations(&matches) {
+        Ok(operations) => operations,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+
+    let policy = matches.value_of("sanitize").map(|filename| {
+        match fs::read_to_string(filename)
+            .map_err(|e| e.to_string())
+            .and_then(|content| sanitize::parse_policy(&content))
+        {
+            Ok(policy) => policy,
+            Err(e) => {
+                eprintln!("{}: {}", filename, e);
+                exit(1);
+            }
+        }
+    });
+
+    // Only files can be replaced, with the whole transformed document so nothing is lost
+    if matches.is_present("in-place") {
+        if !is_transforming(&matches) {
+            eprintln!("--in-place requires a transformation (--delete, --unwrap, --set-attr, --remove-attr, --replace-text, --wrap or --sanitize)");
+            exit(1);
+        }
+        if let Some(input) = inputs
+            .iter()
+            .find(|input| **input == "-" || fetcher::is_url(input))
+        {
+            eprintln!(
+                "--in-place replaces files given with --input, {} can't be replaced",
+                if *input == "-" { "stdin" } else { input }
+            );
+            exit(1);
+        }
+    }
+
+    // Clap conflicts don't depend on values: texts and attributes extracted with --keep-text-only
+    // and --keep-attributes-values can be rendered in yaml or toml only and lines are only wrapped in text
+    let format = matches.value_of("format").unwrap_or_default();
+    let conflict = if matches!(format, "markdown" | "text")
+        && (matches.is_present("keep-text-only") || matches.is_present("keep-attributes-values"))
+    {
+        Some(format!(
+            "The argument '--format {}' cannot be used with '--keep-text-only' or '--keep-attributes-values'\n",
+            format
+        ))
+    } else if matches.is_present("width") && format != "text" {
+        Some(format!(
+            "The argument '--width <COLUMNS>' cannot be used with '--format {}'\n",
+            format
+        ))
+    } else {
+        None
+    };
+    if let Some(conflict) = conflict {
+        clap::Error::with_description(conflict, ErrorKind::ArgumentConflict).exit();
+    }
+
+    // The decision is shared with the colored library, which would otherwise look at the environment on its own
+    colored::control::set_override(is_color_enabled(&matches));
+    if let Ok(spec) = env::var("RUP_COLORS") {
+        if let Err(e) = renderer::theme::parse_theme(&spec) {
+            eprintln!("RUP_COLORS: {}", e);
+            exit(1);
+        }
+    }
+
+    let template = matches.value_of("template").map(|filename| {
+        match fs::read_to_string(filename)
+            .map_err(|e| e.to_string())
+            .and_then(|content| template::parse_template(&content))
+        {
+            Ok(template) => template,
+            Err(e) => {
+                eprintln!("{}: {}", filename, e);
+                exit(1);
+            }
+        }
+    });
+
+    let mut output = match matches.value_of("output") {
+        Some(path) => Output::file(path).unwrap_or_else(|e| exit_with_error(path, e)),
+        None => Output::stdout(),
+    };
+
+    if matches.is_present("stream") {
+        let selector_chain = match selector_chains.as_slice() {
+            [selector_chain] => *selector_chain,
+            _ => {
+                eprintln!("--stream requires a single css selector chain");
+                exit(1);
+            }
+        };
+        let selectors = parser::parse(selector_chain.to_string());
+        if let Err(e) = stream::check_selectors(&selectors) {
+            eprintln!("{}", e);
+            exit(1);
+        }
+
+        inputs.iter().for_each(|input| {
+            let result = fetcher::open(build_fetch_settings(&matches), input).and_then(|mut s| {
+                if let (true, Some(url)) = (matches.is_present("verbose"), &s.url) {
+                    eprintln!("{}", url);
                 }
-                match matches.values_of("keep-attributes-values") {
-                    Some(attributes) => settings_builder.should_render_attributes(
-                        attributes.map(|v| v.to_string()).collect::<Vec<String>>(),
+                stream::filter(
+                    &mut s.reader,
+                    s.encoding,
+                    (selector_chain, &selectors),
+                    build_serialize_settings(
+                        &matches,
+                        input,
+                        find_base_url(&matches, s.url.as_deref(), None),
                     ),
-                    None => (),
+                    &mut output,
+                )
+            });
+            if let Err(e) = result {
+                exit_with_error(input, e);
+            }
+        });
+        finish_output(&matches, output);
+        return;
+    }
+
+    let documents = inputs
+        .into_iter()
+        .map(
+            |input| match fetcher::fetch(build_fetch_settings(&matches), input) {
+                Ok(document) => {
+                    if let (true, Some(url)) = (matches.is_present("verbose"), &document.url) {
+                        eprintln!("{}", url);
+                    }
+                    (input, document)
+                }
+                Err(e) => {
+                    eprintln!("{}: {}", input, e);
+                    exit(1);
                 }
+            },
+        )
+        .collect::<Vec<_>>();
+
+    // Without selectors the nodes read from JSON and the transformed documents are rendered as a whole
+    let selector_chains = if (matches.is_present("from-json") || is_transforming(&matches))
+        && selector_chains.is_empty()
+    {
+        vec![None]
+    } else {
+        selector_chains.into_iter().map(Some).collect()
+    };
 
-                let css_selector = &parser::parse(selector_chain.to_string());
-                let nodes = filter::filter(buffer.clone(), css_selector);
-                println!(
+    if let Some(template) = template {
+        documents.iter().for_each(|(input, document)| {
+            // Extracted values are never colored
+            let mut settings_builder = SerializeSettingsBuilder::new();
+            if matches.is_present("xml") {
+                settings_builder.render_xml();
+            }
+            let dom = parse_document(&matches, &operations, policy.as_ref(), input, document);
+            if let Some(base_url) = find_base_url(&matches, document.url.as_deref(), Some(&dom)) {
+                settings_builder.set_base_url(base_url);
+            }
+            let result = template::extract(&template, &dom, &settings_builder).and_then(|record| {
+                writeln!(
+                    output,
                     "{}",
-                    renderer::serialize_nodes(settings_builder, nodes).unwrap()
-                );
+                    renderer::colorize_json(
+                        build_serialize_settings(&matches, input, None),
+                        &record.to_string()
+                    )
+                )
+            });
+            if let Err(e) = result {
+                exit_with_error(input, e);
+            }
+        });
+        finish_output(&matches, output);
+        return;
+    }
+
+    documents.iter().for_each(|(input, document)| {
+        // Every input file is replaced with the markup rendered from it
+        let mut in_place_file = matches
+            .is_present("in-place")
+            .then(|| AtomicFile::create(input).unwrap_or_else(|e| exit_with_error(input, e)));
+        let document_output: &mut dyn Write = match &mut in_place_file {
+            Some(file) => file,
+            None => &mut output,
+        };
+
+        let dom = parse_document(&matches, &operations, policy.as_ref(), input, document);
+        let base_url = find_base_url(&matches, document.url.as_deref(), Some(&dom));
+        selector_chains.iter().for_each(|selector_chain| {
+            let mut settings_builder = build_serialize_settings(&matches, input, base_url.clone());
+
+            let nodes = match selector_chain {
+                Some(selector_chain) => {
+                    settings_builder.set_selector(selector_chain.to_string());
+                    filter::filter(&dom, &parser::parse(selector_chain.to_string()))
+                }
+                // The transformed document is rendered at once, its root nodes aren't separated with new lines
+                None if is_transforming(&matches) && is_rendering_markup(&matches) => {
+                    vec![dom.to_owned()]
+                }
+                None => dom.children.borrow().to_vec(),
+            };
+            let result = match matches.value_of("table") {
+                Some(table_format) => match table_format {
+                    "csv" => table::serialize_tables(TableFormat::Csv, &nodes),
+                    "tsv" => table::serialize_tables(TableFormat::Tsv, &nodes),
+                    _ => table::serialize_tables(TableFormat::Json, &nodes)
+                        .map(|json| renderer::colorize_json(settings_builder, &json)),
+                }
+                .and_then(|tables| document_output.write_all(tables.as_bytes()))
+                .map(|_| true),
+                None => renderer::write_nodes(settings_builder, nodes, document_output),
+            }
+            // Like in streaming mode, a selector matching nothing doesn't output an empty line
+            .and_then(|is_written| {
+                if is_written {
+                    writeln!(document_output)
+                } else {
+                    Ok(())
+                }
             });
-            ()
+            if let Err(e) = result {
+                exit_with_error(input, e);
+            }
+        });
+
+        if let Some(file) = in_place_file {
+            if let Err(e) = file.commit(matches.value_of("in-place")) {
+                exit_with_error(input, e);
+            }
+        }
+    });
+    finish_output(&matches, output);
+}
+
+// Like sed, the backup suffix is glued to the short flag (e.g. -i.bak), which clap only understands as -i=.bak
+fn glue_in_place_suffix(args: impl Iterator<Item = OsString>) -> Vec<OsString> {
+    let mut is_option = true;
+    args.map(|arg| match arg.to_str() {
+        Some("--") => {
+            is_option = false;
+            arg
+        }
+        Some(flag)
+            if is_option
+                && flag.starts_with("-i")
+                && !flag.starts_with("-i=")
+                && flag.len() > 2 =>
+        {
+            OsString::from(format!("--in-place={}", &flag[2..]))
         }
-        None => (),
+        _ => arg,
+    })
+    .collect()
+}
+
+fn finish_output(matches: &ArgMatches, output: Output) {
+    if let Err(e) = output.finish() {
+        exit_with_error(matches.value_of("output").unwrap_or("stdout"), e);
+    }
+}
+
+// The output may be closed by its reader, e.g. head, the error isn't worth reporting then
+fn exit_with_error(input: &str, e: io::Error) -> ! {
+    if e.kind() != io::ErrorKind::BrokenPipe {
+        eprintln!("{}: {}", input, e);
+    }
+    exit(1);
+}
+
+// Nothing is dropped on exit, the temporary files of the outputs not written yet are removed beforehand
+fn exit(code: i32) -> ! {
+    output::remove_temporary_files();
+    process::exit(code);
+}
+
+// The transformations are applied once the document is parsed, then the document is sanitized
+fn parse_document(
+    matches: &ArgMatches,
+    operations: &[Operation],
+    policy: Option<&Policy>,
+    input: &str,
+    document: &Document,
+) -> Handle {
+    let dom = if matches.is_present("from-json") {
+        match renderer::deserialize_nodes(&document.content) {
+            Ok(dom) => dom,
+            Err(e) => {
+                eprintln!("{}: {}", input, e);
+                exit(1);
+            }
+        }
+    } else if matches.is_present("xml") {
+        filter::parse_xml(&document.content)
+    } else if matches.is_present("fragment") {
+        filter::parse_html_fragment(
+            &document.content,
+            matches.value_of("fragment").unwrap_or("template"),
+        )
+    } else {
+        filter::parse_html(&document.content)
     };
+    transform::transform(&dom, operations);
+    if let Some(policy) = policy {
+        let report = sanitize::sanitize(&dom, policy);
+        for line in report.to_string().lines() {
+            eprintln!("{}: {}", input, line);
+        }
+    }
+    dom
+}
+
+// The transformations are sorted in the order they are provided, whatever their kind
+fn build_operations(matches: &ArgMatches) -> Result<Vec<Operation>, String> {
+    let mut operations = vec![];
+    for name in [
+        "delete",
+        "unwrap",
+        "set-attr",
+        "remove-attr",
+        "replace-text",
+        "wrap",
+    ] {
+        let (values, indices) = match (matches.values_of(name), matches.indices_of(name)) {
+            (Some(values), Some(indices)) => (
+                values.collect::<Vec<&str>>(),
+                indices.collect::<Vec<usize>>(),
+            ),
+            _ => continue,
+        };
+        let arity = if matches!(name, "delete" | "unwrap") {
+            1
+        } else {
+            2
+        };
+        for (values, index) in values.chunks(arity).zip(indices.into_iter().step_by(arity)) {
+            let transformation = match (name, values) {
+                ("delete", _) => Transformation::Delete,
+                ("unwrap", _) => Transformation::Unwrap,
+                ("set-attr", [_, assignment]) => transform::parse_assignment(assignment)
+                    .map_err(|e| format!("--set-attr: {}", e))?,
+                ("remove-attr", [_, name]) => Transformation::RemoveAttribute(name.to_string()),
+                ("replace-text", [_, text]) => Transformation::ReplaceText(text.to_string()),
+                (_, [_, element]) => {
+                    transform::parse_wrapper(element).map_err(|e| format!("--wrap: {}", e))?
+                }
+                _ => unreachable!(),
+            };
+            operations.push((index, Operation::new(values[0], transformation)));
+        }
+    }
+
+    operations.sort_by_key(|(index, _)| *index);
+    Ok(operations
+        .into_iter()
+        .map(|(_, operation)| operation)
+        .collect())
+}
+
+fn is_transforming(matches: &ArgMatches) -> bool {
+    [
+        "delete",
+        "unwrap",
+        "set-attr",
+        "remove-attr",
+        "replace-text",
+        "wrap",
+        "sanitize",
+    ]
+    .iter()
+    .any(|name| matches.is_present(name))
+}
+
+// Whether the nodes are rendered as html or xml, not extracted nor converted to another format
+fn is_rendering_markup(matches: &ArgMatches) -> bool {
+    ![
+        "keep-text-only",
+        "keep-attributes-values",
+        "json",
+        "ndjson",
+        "format",
+        "format-string",
+        "table",
+        "template",
+    ]
+    .iter()
+    .any(|name| matches.is_present(name))
+}
+
+// The url of the document is used as base url when none is provided, the <base href> element
+// of the document takes precedence once the document is parsed
+fn find_base_url(
+    matches: &ArgMatches,
+    document_url: Option<&str>,
+    document: Option<&Handle>,
+) -> Option<Url> {
+    let url = matches
+        .value_of("base-url")
+        .or(document_url)
+        .and_then(|url| Url::parse(url).ok());
+    match document {
+        Some(document) => renderer::find_base_url(url, document),
+        None => url,
+    }
+}
+
+fn build_fetch_settings(matches: &ArgMatches) -> FetchSettingsBuilder {
+    let mut settings_builder = FetchSettingsBuilder::new();
+    if let Some(headers) = matches.values_of("header") {
+        headers.for_each(|header| {
+            let (name, value) = header.split_once(':').unwrap();
+            settings_builder.add_header(name.trim().to_string(), value.trim().to_string())
+        });
+    }
+    if let Some(user_agent) = matches.value_of("user-agent") {
+        settings_builder.set_user_agent(user_agent.to_string());
+    }
+    if let Some(cookies) = matches.values_of("cookie") {
+        cookies.for_each(|cookie| settings_builder.add_cookie(cookie.to_string()));
+    }
+    if let Some(timeout) = matches.value_of("timeout") {
+        settings_builder.set_timeout(Duration::from_secs(timeout.parse().unwrap()));
+    }
+    settings_builder
+}
+
+fn is_color_enabled(matches: &ArgMatches) -> bool {
+    let is_env_defined = |name: &str| env::var_os(name).is_some_and(|value| !value.is_empty());
+    match matches.value_of("color") {
+        _ if matches.is_present("no-color") => false,
+        Some("always") => true,
+        Some("never") => false,
+        _ => {
+            !is_env_defined("NO_COLOR")
+                && (env::var("CLICOLOR_FORCE").is_ok_and(|value| value != "0")
+                    || io::stdout().is_terminal())
+        }
+    }
+}
+
+fn build_serialize_settings(
+    matches: &ArgMatches,
+    input: &str,
+    base_url: Option<Url>,
+) -> SerializeSettingsBuilder {
+    let mut settings_builder = SerializeSettingsBuilder::new();
+    if is_color_enabled(matches) {
+        settings_builder.enable_color();
+    }
+    if let Ok(spec) = env::var("RUP_COLORS") {
+        settings_builder.set_theme(renderer::theme::parse_theme(&spec).unwrap());
+    }
+    if matches.is_present("keep-text-only") {
+        settings_builder.should_render_text_only();
+    }
+    match matches.value_of("text-mode") {
+        Some("own") => settings_builder.set_text_mode(TextMode::Own),
+        Some("inner") => settings_builder.set_text_mode(TextMode::Inner),
+        _ => (),
+    }
+    if matches.is_present("text-nodes") {
+        settings_builder.render_text_node_per_line();
+    }
+    if matches.is_present("json") {
+        settings_builder.render_json();
+    }
+    match matches.value_of("json-format") {
+        Some("pup") => settings_builder.set_json_format(JsonFormat::Pup),
+        Some("lossless") => settings_builder.set_json_format(JsonFormat::Lossless),
+        _ => (),
+    }
+    if matches.is_present("xml") {
+        settings_builder.render_xml();
+    }
+    match matches.value_of("format") {
+        Some("yaml") => settings_builder.render_yaml(),
+        Some("toml") => settings_builder.render_toml(),
+        Some("markdown") => settings_builder.render_markdown(),
+        Some("text") => settings_builder.render_text(),
+        _ => (),
+    }
+    if let Some(width) = matches.value_of("width") {
+        settings_builder.set_width(renderer::parse_width(width).unwrap());
+    }
+    if matches.is_present("ndjson") {
+        settings_builder.render_ndjson();
+    }
+    if matches.is_present("with-selector") {
+        settings_builder.annotate_selector();
+    }
+    if matches.is_present("with-source") || matches.is_present("format-string") {
+        settings_builder.annotate_source(input.to_string());
+    }
+    if let Some(format_string) = matches.value_of("format-string") {
+        settings_builder.set_format_string(renderer::parse_format_string(format_string).unwrap());
+    }
+    // A transformed document is rendered as found so it only differs from the input by the transformed nodes,
+    // unless the markup is laid out or something else is extracted
+    let is_rendering_markup_as_found = is_transforming(matches)
+        && is_rendering_markup(matches)
+        && !["pretty", "indent", "minify", "base-url"]
+            .iter()
+            .any(|name| matches.is_present(name));
+    if matches.is_present("raw") || is_rendering_markup_as_found {
+        settings_builder.render_raw();
+    }
+    if matches.is_present("minify") {
+        settings_builder.render_minified();
+    }
+    if matches.is_present("keep-comments") {
+        settings_builder.keep_comments();
+    }
+    if let Some(indent) = matches.value_of("indent") {
+        settings_builder.set_indent(indent.parse().unwrap());
+    } else if matches.is_present("pretty") {
+        settings_builder.set_indent(2);
+    }
+    match matches.value_of("attributes-format") {
+        Some("json") => settings_builder.set_attributes_format(AttributesFormat::Json),
+        Some("tsv") => settings_builder.set_attributes_format(AttributesFormat::Tsv),
+        _ => (),
+    }
+    if let Some(attributes) = matches.values_of("keep-attributes-values") {
+        settings_builder
+            .should_render_attributes(attributes.map(|v| v.to_string()).collect::<Vec<String>>())
+    }
+    if let Some(base_url) = base_url {
+        settings_builder.set_base_url(base_url);
+    }
+    settings_builder
 }
@@ -0,0 +1,252 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+// The temporary files not yet renamed, they are removed when the process exits on an error
+static TEMPORARY_FILES: Mutex<Vec<PathBuf>> = Mutex::new(vec![]);
+static TEMPORARY_FILES_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// Where the rendered nodes are written, stdout or a file replaced once everything is written
+pub enum Output {
+    Stdout(Box<dyn Write>),
+    File(AtomicFile),
+}
+
+impl Output {
+    // The output is buffered unless it is read by someone, the results are then seen as soon as they are rendered
+    pub fn stdout() -> Output {
+        let stdout = io::stdout();
+        Output::Stdout(if stdout.is_terminal() {
+            Box::new(stdout.lock())
+        } else {
+            Box::new(BufWriter::new(stdout.lock()))
+        })
+    }
+
+    pub fn file(path: &str) -> io::Result<Output> {
+        AtomicFile::create(path).map(Output::File)
+    }
+
+    // Flushes stdout or moves the file to its final place
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Stdout(mut stdout) => stdout.flush(),
+            Output::File(file) => file.commit(None),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout(stdout) => stdout.write(buf),
+            Output::File(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(stdout) => stdout.flush(),
+            Output::File(file) => file.flush(),
+        }
+    }
+}
+
+// A file written in a temporary file of the same directory and renamed over the original file once complete,
+// so the original file is either left intact or entirely replaced even if the process is interrupted
+pub struct AtomicFile {
+    path: PathBuf,
+    temporary_path: PathBuf,
+    file: Option<BufWriter<File>>,
+}
+
+impl AtomicFile {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<AtomicFile> {
+        // A symbolic link is kept, the file it points to is replaced instead
+        let path = match fs::canonicalize(path.as_ref()) {
+            Ok(path) => path,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => path.as_ref().to_path_buf(),
+            Err(e) => return Err(e),
+        };
+        let filename = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?;
+        let temporary_path = path.with_file_name(format!(
+            ".{}.rup-{}-{}.tmp",
+            filename.to_string_lossy(),
+            process::id(),
+            TEMPORARY_FILES_COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temporary_path)?;
+        TEMPORARY_FILES
+            .lock()
+            .unwrap()
+            .push(temporary_path.to_owned());
+        // The replaced file keeps its permissions
+        if let Ok(metadata) = fs::metadata(&path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+
+        Ok(AtomicFile {
+            path,
+            temporary_path,
+            file: Some(BufWriter::new(file)),
+        })
+    }
+
+    // Replaces the original file, which is copied first to a file named with the given suffix, like sed -i.bak
+    pub fn commit(mut self, backup_suffix: Option<&str>) -> io::Result<()> {
+        let file = match self.file.take() {
+            Some(file) => file.into_inner().map_err(|e| e.into_error())?,
+            None => return Ok(()),
+        };
+        // The content must be on the disk before the rename is, otherwise a crash could leave an empty file
+        file.sync_all()?;
+
+        if let Some(suffix) = backup_suffix.filter(|suffix| !suffix.is_empty()) {
+            let mut backup_path = self.path.to_owned().into_os_string();
+            backup_path.push(suffix);
+            fs::copy(&self.path, backup_path)?;
+        }
+        fs::rename(&self.temporary_path, &self.path)?;
+        forget_temporary_file(&self.temporary_path);
+
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.file {
+            Some(file) => file.write(buf),
+            None => Err(io::Error::other("the file is already committed")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for AtomicFile {
+    // A file dropped before being committed is abandoned, the original file is untouched
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.temporary_path);
+            forget_temporary_file(&self.temporary_path);
+        }
+    }
+}
+
+fn forget_temporary_file(temporary_path: &Path) {
+    TEMPORARY_FILES
+        .lock()
+        .unwrap()
+        .retain(|path| path != temporary_path);
+}
+
+// Nothing is dropped when the process exits, so the temporary files are removed beforehand
+pub fn remove_temporary_files() {
+    for path in TEMPORARY_FILES.lock().unwrap().drain(..) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use std::{env, fs, io::Write};
+
+    use super::*;
+
+    #[test]
+    fn write_atomic_files() {
+        let scenarios = vec![
+            (
+                // Create a file which didn't exist
+                None,
+                Some(None),
+                Some("<p>new</p>"),
+                None,
+            ),
+            (
+                // Replace an existing file
+                Some("<p>old</p>"),
+                Some(None),
+                Some("<p>new</p>"),
+                None,
+            ),
+            (
+                // Keep a copy of the replaced file
+                Some("<p>old</p>"),
+                Some(Some(".bak")),
+                Some("<p>new</p>"),
+                Some("<p>old</p>"),
+            ),
+            (
+                // Leave the file untouched when the new content is abandoned
+                Some("<p>old</p>"),
+                None,
+                Some("<p>old</p>"),
+                None,
+            ),
+            (
+                // Create nothing when the new content is abandoned
+                None, None, None, None,
+            ),
+        ];
+
+        let directory = env::temp_dir().join(format!("rup-output-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        for (index, (original, commit, expected, expected_backup)) in
+            scenarios.into_iter().enumerate()
+        {
+            let path = directory.join(format!("{}.html", index));
+            if let Some(original) = original {
+                fs::write(&path, original).unwrap();
+            }
+
+            let mut file = AtomicFile::create(&path).unwrap();
+            file.write_all(b"<p>new</p>").unwrap();
+            if let Some(backup_suffix) = commit {
+                file.commit(backup_suffix).unwrap();
+            } else {
+                drop(file);
+            }
+
+            assert_eq!(fs::read_to_string(&path).ok().as_deref(), expected);
+            assert_eq!(
+                fs::read_to_string(directory.join(format!("{}.html.bak", index)))
+                    .ok()
+                    .as_deref(),
+                expected_backup
+            );
+        }
+
+        // No temporary file is left behind
+        let mut filenames = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<String>>();
+        filenames.sort();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(
+            filenames,
+            vec!["0.html", "1.html", "2.html", "2.html.bak", "3.html"]
+        );
+    }
+}
@@ -40,9 +40,10 @@ pub enum CssSelectorAttribute {
 }
 
 // Represents a css combinator (e.g. : A B, A + B, A > B)
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub enum CssCombinator {
     // Represents the space combinator that selects nodes that are descendants of the first element, A B
+    #[default]
     Descendant,
     // Represents the child combinator that selects nodes that are direct children of the first element, A > B
     DirectChild,
@@ -50,12 +51,6 @@ pub enum CssCombinator {
     AdjacentSibling,
 }
 
-impl Default for CssCombinator {
-    fn default() -> Self {
-        CssCombinator::Descendant
-    }
-}
-
 // Represents a css selector (e.g. div#id, div.class, ....)
 #[derive(Debug, Default, Clone, PartialEq)]
 pub struct CssSelector {
@@ -117,8 +112,10 @@ pub fn parse(expression: String) -> Vec<CssSelector> {
         let mut pseudo_class_name = String::default();
         let mut pseudo_class_nth_child_value = String::default();
         let mut previous_char = char::default();
-        let mut current_node = CssSelector::default();
-        current_node.combinator = current_node_combinator.to_owned();
+        let mut current_node = CssSelector {
+            combinator: current_node_combinator.to_owned(),
+            ..Default::default()
+        };
         current_node_combinator = CssCombinator::default();
 
         for c in expression_parsed.chars() {
@@ -182,34 +179,29 @@ pub fn parse(expression: String) -> Vec<CssSelector> {
                 }
                 // The attr (e.g. 2 in div:nth-child(2)) is used as a marker, if it's not defined we have to add any character to define the pseudo-class
                 // if it's defined, it means we are collecting characters for the attribute
-                CssSelectorAttribute::PseudoClass(PseudoClass::Empty) => match c {
-                    _ => {
-                        pseudo_class_name.push(c);
-                        match pseudo_class_name.as_str() {
-                            "first-child" => {
-                                current_node_attribute = CssSelectorAttribute::PseudoClass(
-                                    parser::PseudoClass::FirstChild,
-                                )
-                            }
-                            "last-child" => {
-                                current_node_attribute = CssSelectorAttribute::PseudoClass(
-                                    parser::PseudoClass::LastChild,
-                                )
-                            }
-                            "first-of-type" => {
-                                current_node_attribute = CssSelectorAttribute::PseudoClass(
-                                    parser::PseudoClass::FirstOfType,
-                                )
-                            }
-                            "nth-child" => {
-                                current_node_attribute = CssSelectorAttribute::PseudoClass(
-                                    parser::PseudoClass::NthChild(0, 0),
-                                )
-                            }
-                            _ => {}
+                CssSelectorAttribute::PseudoClass(PseudoClass::Empty) => {
+                    pseudo_class_name.push(c);
+                    match pseudo_class_name.as_str() {
+                        "first-child" => {
+                            current_node_attribute =
+                                CssSelectorAttribute::PseudoClass(parser::PseudoClass::FirstChild)
+                        }
+                        "last-child" => {
+                            current_node_attribute =
+                                CssSelectorAttribute::PseudoClass(parser::PseudoClass::LastChild)
+                        }
+                        "first-of-type" => {
+                            current_node_attribute =
+                                CssSelectorAttribute::PseudoClass(parser::PseudoClass::FirstOfType)
+                        }
+                        "nth-child" => {
+                            current_node_attribute = CssSelectorAttribute::PseudoClass(
+                                parser::PseudoClass::NthChild(0, 0),
+                            )
                         }
+                        _ => {}
                     }
-                },
+                }
                 CssSelectorAttribute::PseudoClass(PseudoClass::NthChild(a, _)) => match c {
                     '(' => {
                         previous_char = c;
@@ -232,7 +224,7 @@ pub fn parse(expression: String) -> Vec<CssSelector> {
                                     parser::PseudoClass::NthChild(a, 0),
                                 )
                             }
-                            v @ _ => {
+                            v => {
                                 current_node_attribute = CssSelectorAttribute::PseudoClass(
                                     parser::PseudoClass::NthChild(a, v.parse::<i32>().unwrap()),
                                 )
@@ -253,7 +245,7 @@ pub fn parse(expression: String) -> Vec<CssSelector> {
                                     parser::PseudoClass::NthChild(-1, 0),
                                 )
                             }
-                            v @ _ if v.chars().nth(0).unwrap() == '-' => {
+                            v if v.starts_with('-') => {
                                 current_node_attribute = CssSelectorAttribute::PseudoClass(
                                     parser::PseudoClass::NthChild(
                                         -v.chars()
@@ -265,7 +257,7 @@ pub fn parse(expression: String) -> Vec<CssSelector> {
                                     ),
                                 );
                             }
-                            v @ _ => {
+                            v => {
                                 current_node_attribute = CssSelectorAttribute::PseudoClass(
                                     parser::PseudoClass::NthChild(v.parse::<i32>().unwrap(), 0),
                                 );
@@ -329,7 +321,7 @@ pub fn parse(expression: String) -> Vec<CssSelector> {
                             previous_char = c;
                             continue;
                         }
-                        '*' | '$' | '^' | '~' if right_operand == &None => {
+                        '*' | '$' | '^' | '~' if right_operand.is_none() => {
                             previous_char = c;
                             continue;
                         }
@@ -377,11 +369,38 @@ pub fn parse(expression: String) -> Vec<CssSelector> {
     nodes
 }
 
+// Parse a comma separated list of css selectors (e.g. script, .ad), a node matching any of them is matched
+pub fn parse_groups(expression: &str) -> Vec<Vec<CssSelector>> {
+    let mut groups = vec![];
+    let mut acc = String::new();
+    let mut open_square_bracket_detected = false;
+
+    for c in expression.chars() {
+        match c {
+            ',' if !open_square_bracket_detected => {
+                groups.push(parse(acc.trim().to_string()));
+                acc.clear();
+                continue;
+            }
+            '[' => open_square_bracket_detected = true,
+            ']' => open_square_bracket_detected = false,
+            _ => (),
+        }
+        acc.push(c);
+    }
+    groups.push(parse(acc.trim().to_string()));
+
+    groups
+        .into_iter()
+        .filter(|group| !group.is_empty())
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use crate::parser::{self, CssCombinator, PseudoClass};
 
-    use super::{parse, AttributeSign, CssSelector, CssSelectorAttribute};
+    use super::{parse, parse_groups, AttributeSign, CssSelector, CssSelectorAttribute};
     use pretty_assertions::assert_eq;
 
     #[test]
@@ -549,4 +568,28 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn parse_selector_groups() {
+        let scenarios = vec![
+            ("script, .ad", vec!["script", ".ad"]),
+            // Empty groups are dropped
+            ("a,,b", vec!["a", "b"]),
+            (" , ", vec![]),
+            // A trailing comma doesn't add a group
+            ("div p,", vec!["div p"]),
+            // A comma in an attribute selector doesn't separate groups
+            ("[title='a,b'], p", vec!["[title='a,b']", "p"]),
+        ];
+
+        for (expression, groups) in scenarios {
+            assert_eq!(
+                parse_groups(expression),
+                groups
+                    .into_iter()
+                    .map(|group| parse(group.to_string()))
+                    .collect::<Vec<Vec<CssSelector>>>()
+            );
+        }
+    }
 }
@@ -0,0 +1,854 @@
+use html5ever::{
+    serialize::{AttrRef, Serializer, TraversalScope},
+    QualName,
+};
+use markup5ever::{local_name, namespace_url, ns};
+use markup5ever_rcdom::Node;
+use regex::Regex;
+use std::{collections::HashMap, io, rc::Rc, sync::LazyLock};
+
+use super::{is_block_element, resolve_url, traverse, SerializeSettings};
+
+const DEFAULT_TEXT_WIDTH: usize = 80;
+
+// Every node is laid out as a markdown or a text document, documents are separated with an empty line
+pub fn serialize_node_to_layout(
+    settings: &SerializeSettings,
+    layout: Layout,
+    node: &Rc<Node>,
+) -> io::Result<Option<String>> {
+    let mut buffer = String::new();
+
+    let mut ser = LayoutSerializer::new(settings.to_owned(), layout, &mut buffer);
+    traverse(&mut ser, node, TraversalScope::IncludeNode)?;
+    ser.finish();
+
+    Ok((!buffer.is_empty()).then_some(buffer))
+}
+
+// The renderings made of text laid out in blocks instead of markup
+#[derive(Clone, Copy, PartialEq)]
+pub enum Layout {
+    // CommonMark with the GitHub flavored extensions (tables and strikethrough)
+    Markdown,
+    // Readable plain text like lynx -dump, links are listed as references at the end
+    Text,
+}
+
+// How an element is laid out
+#[derive(Clone, PartialEq)]
+enum LayoutElement {
+    // The content of the element flows in its parent
+    Inline,
+    // The children of the element are blocks separated with an empty line
+    Block,
+    Heading(usize),
+    // Emphasis, strong emphasis and strikethrough wrap their content with the given markdown marker
+    Emphasis(&'static str),
+    Code,
+    Preformatted {
+        language: Option<String>,
+    },
+    Link {
+        href: Option<String>,
+        title: Option<String>,
+    },
+    Image {
+        src: Option<String>,
+        alt: String,
+        title: Option<String>,
+    },
+    LineBreak,
+    ThematicBreak,
+    List {
+        is_ordered: bool,
+        // The number of the next item of an ordered list
+        number: usize,
+    },
+    ListItem {
+        marker: String,
+    },
+    Blockquote,
+    Table,
+    TableSection {
+        is_head: bool,
+    },
+    TableRow,
+    TableCell {
+        is_header: bool,
+        colspan: usize,
+    },
+    // The element and its content are left out (e.g. script, style)
+    Ignored,
+}
+
+// An element being laid out, its content is made of blocks and of the inline content following the last block
+struct LayoutFrame {
+    element: LayoutElement,
+    // The laid out blocks along with whether the block is a list
+    blocks: Vec<(String, bool)>,
+    inline: String,
+    // A line break is only written when some content follows it
+    is_line_break_pending: bool,
+    // Used by tables, the cells of every row along with whether the row is a header row
+    rows: Vec<(Vec<String>, bool)>,
+    // Used by table rows
+    cells: Vec<(String, bool)>,
+}
+
+impl LayoutFrame {
+    fn new(element: LayoutElement) -> Self {
+        LayoutFrame {
+            element,
+            blocks: vec![],
+            inline: String::new(),
+            is_line_break_pending: false,
+            rows: vec![],
+            cells: vec![],
+        }
+    }
+
+    // The content of an inline element holding blocks is written on a single line
+    fn join_inline(&mut self) -> String {
+        let inline = std::mem::take(&mut self.inline);
+        self.blocks
+            .drain(..)
+            .map(|(block, _)| block)
+            .chain(std::iter::once(inline))
+            .filter(|content| !content.trim().is_empty())
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    // The width taken by the prefix of every line of the element content
+    fn prefix_width(&self) -> usize {
+        match &self.element {
+            LayoutElement::ListItem { marker } => marker.len(),
+            LayoutElement::Blockquote => 2,
+            _ => 0,
+        }
+    }
+}
+
+// Lays out html as markdown or as plain text, elements without equivalent are replaced with their content
+pub struct LayoutSerializer<'a> {
+    settings: SerializeSettings,
+    layout: Layout,
+    stack: Vec<LayoutFrame>,
+    // Used in text layout, the urls of the links in order of appearance
+    references: Vec<String>,
+    buffer: &'a mut String,
+}
+
+impl<'a> LayoutSerializer<'a> {
+    fn new(settings: SerializeSettings, layout: Layout, buffer: &'a mut String) -> Self {
+        LayoutSerializer {
+            settings,
+            layout,
+            stack: vec![LayoutFrame::new(LayoutElement::Block)],
+            references: vec![],
+            buffer,
+        }
+    }
+
+    // Writes the laid out blocks to the buffer once the whole tree is serialized
+    fn finish(mut self) {
+        let content = self.join_blocks();
+        self.buffer.push_str(content.as_str());
+
+        if !self.references.is_empty() {
+            let references = self
+                .references
+                .iter()
+                .enumerate()
+                .map(|(index, url)| format!("[{}] {}", index + 1, url))
+                .collect::<Vec<String>>()
+                .join("\n");
+            if !self.buffer.is_empty() {
+                self.buffer.push_str("\n\n");
+            }
+            self.buffer.push_str("References\n\n");
+            self.buffer.push_str(references.as_str());
+        }
+    }
+
+    fn parent(&mut self) -> &mut LayoutFrame {
+        if self.stack.is_empty() {
+            self.stack.push(LayoutFrame::new(LayoutElement::Block));
+        }
+        self.stack.last_mut().unwrap()
+    }
+
+    fn is_ignoring(&self) -> bool {
+        self.stack
+            .iter()
+            .any(|frame| frame.element == LayoutElement::Ignored)
+    }
+
+    // Texts of code and preformatted elements are written as is
+    fn is_literal(&self) -> bool {
+        self.stack.iter().any(|frame| {
+            matches!(
+                frame.element,
+                LayoutElement::Code | LayoutElement::Preformatted { .. }
+            )
+        })
+    }
+
+    fn is_in_table_cell(&self) -> bool {
+        self.stack
+            .iter()
+            .any(|frame| matches!(frame.element, LayoutElement::TableCell { .. }))
+    }
+
+    // In text layout paragraphs are wrapped to the width left by the prefixes of the enclosing elements,
+    // table cells are never wrapped
+    fn content_width(&self) -> Option<usize> {
+        if self.layout != Layout::Text || self.is_in_table_cell() {
+            return None;
+        }
+        let prefix_width = self
+            .stack
+            .iter()
+            .map(|frame| frame.prefix_width())
+            .sum::<usize>();
+        Some(
+            self.settings
+                .width
+                .unwrap_or(DEFAULT_TEXT_WIDTH)
+                .saturating_sub(prefix_width)
+                .max(1),
+        )
+    }
+
+    fn resolve_url(&self, name: &str, value: Option<String>) -> Option<String> {
+        value.map(|value| resolve_url(&self.settings.base_url, name, value.trim()))
+    }
+
+    fn find_frame(&mut self, is_searched: fn(&LayoutElement) -> bool) -> Option<&mut LayoutFrame> {
+        self.stack
+            .iter_mut()
+            .rev()
+            .find(|frame| is_searched(&frame.element))
+    }
+
+    fn append_inline(&mut self, text: &str) {
+        let line_break = match self.layout {
+            Layout::Markdown => "\\\n",
+            Layout::Text => "\n",
+        };
+        let parent = self.parent();
+        let mut text = text;
+        if text.trim().is_empty() && (parent.inline.is_empty() || parent.inline.ends_with(' ')) {
+            return;
+        }
+        if parent.is_line_break_pending && !text.trim().is_empty() {
+            parent.is_line_break_pending = false;
+            parent.inline.truncate(parent.inline.trim_end().len());
+            parent.inline.push_str(line_break);
+            text = text.trim_start();
+        }
+        // Whitespaces are collapsed across elements
+        if parent.inline.is_empty() || parent.inline.ends_with([' ', '\n']) {
+            text = text.trim_start_matches(' ');
+        }
+        parent.inline.push_str(text);
+    }
+
+    // The inline content of the current element becomes a paragraph
+    fn flush_inline(&mut self) {
+        let content_width = self.content_width();
+        let layout = self.layout;
+        let parent = self.parent();
+        let paragraph = match (layout, content_width) {
+            (Layout::Markdown, _) => escape_markdown_line_start(parent.inline.trim()),
+            (Layout::Text, Some(width)) => wrap_text(parent.inline.trim(), width),
+            (Layout::Text, None) => parent.inline.trim().to_string(),
+        };
+        if !paragraph.is_empty() {
+            parent.blocks.push((paragraph, false));
+        }
+        parent.inline.clear();
+        parent.is_line_break_pending = false;
+    }
+
+    fn push_block(&mut self, block: String, is_list: bool) {
+        self.flush_inline();
+        if !block.is_empty() {
+            self.parent().blocks.push((block, is_list));
+        }
+    }
+
+    // Blocks are separated with an empty line, a list nested in a list item and the items of a list
+    // only start on a new line
+    fn join_blocks(&mut self) -> String {
+        self.flush_inline();
+        let parent = self.parent();
+        parent
+            .blocks
+            .iter()
+            .enumerate()
+            .fold(String::new(), |acc, (index, (block, is_list))| {
+                let separator = match (index, &parent.element) {
+                    (0, _) => "",
+                    (_, LayoutElement::ListItem { .. }) if *is_list => "\n",
+                    (_, LayoutElement::List { .. }) => "\n",
+                    _ => "\n\n",
+                };
+                acc + separator + block
+            })
+    }
+
+    // Lays out the content of the element being closed, the element is still on top of the stack
+    fn close_element(&mut self, element: LayoutElement) {
+        let is_in_table_cell = self.stack.len() > 1
+            && self.stack[..self.stack.len() - 1]
+                .iter()
+                .any(|frame| matches!(frame.element, LayoutElement::TableCell { .. }));
+
+        match element {
+            LayoutElement::Ignored => {
+                self.stack.pop();
+            }
+            LayoutElement::Inline => {
+                let mut frame = self.stack.pop().unwrap();
+                let content = frame.join_inline();
+                self.append_inline(content.as_str());
+                self.parent().is_line_break_pending |= frame.is_line_break_pending;
+            }
+            LayoutElement::Block => {
+                self.flush_inline();
+                let mut frame = self.stack.pop().unwrap();
+                self.flush_inline();
+                self.parent().blocks.append(&mut frame.blocks);
+            }
+            LayoutElement::Heading(level) => {
+                let content = self.parent().join_inline();
+                self.stack.pop();
+                let content = content.replace("\\\n", " ").replace('\n', " ");
+                let heading = match (content.trim(), self.layout) {
+                    ("", _) => String::new(),
+                    (content, Layout::Markdown) => format!("{} {}", "#".repeat(level), content),
+                    (content, Layout::Text) => match self.content_width() {
+                        Some(width) => wrap_text(content, width),
+                        None => content.to_string(),
+                    },
+                };
+                self.push_block(heading, false);
+            }
+            LayoutElement::Emphasis(marker) => {
+                let content = self.parent().join_inline();
+                self.stack.pop();
+                match (content.trim(), self.layout) {
+                    ("", _) | (_, Layout::Text) => self.append_inline(content.as_str()),
+                    (trimmed_content, Layout::Markdown) => {
+                        // Markers can't be separated from the content by a whitespace
+                        if content.starts_with(' ') {
+                            self.append_inline(" ");
+                        }
+                        self.append_inline(
+                            format!("{}{}{}", marker, trimmed_content, marker).as_str(),
+                        );
+                        if content.ends_with(' ') {
+                            self.append_inline(" ");
+                        }
+                    }
+                }
+            }
+            LayoutElement::Code => {
+                let content = self.parent().join_inline().replace('\n', " ");
+                self.stack.pop();
+                if content.is_empty() {
+                    return;
+                }
+                if self.layout == Layout::Text {
+                    self.append_inline(collapse_whitespaces(content.as_str()).as_str());
+                    return;
+                }
+                let fence = "`".repeat(longest_backtick_run(content.as_str()) + 1);
+                // A space is added when the code starts or ends with a backtick
+                let padding = if content.starts_with('`') || content.ends_with('`') {
+                    " "
+                } else {
+                    ""
+                };
+                self.append_inline(
+                    format!("{}{}{}{}{}", fence, padding, content, padding, fence).as_str(),
+                );
+            }
+            LayoutElement::Preformatted { language } => {
+                let content = self.parent().join_inline();
+                self.stack.pop();
+                let content = content.trim_end_matches('\n');
+                let block = match self.layout {
+                    Layout::Markdown => {
+                        let fence = "`".repeat(longest_backtick_run(content).max(2) + 1);
+                        format!(
+                            "{}{}\n{}\n{}",
+                            fence,
+                            language.unwrap_or_default(),
+                            content,
+                            fence
+                        )
+                    }
+                    Layout::Text => content.trim_start_matches('\n').to_string(),
+                };
+                self.push_block(block, false);
+            }
+            LayoutElement::Link { href, title } => {
+                let content = self.parent().join_inline();
+                self.stack.pop();
+                match (href, self.layout) {
+                    (Some(href), Layout::Markdown) => {
+                        let text = match content.trim() {
+                            "" => escape_markdown(href.as_str(), is_in_table_cell),
+                            text => text.to_string(),
+                        };
+                        self.append_inline(
+                            format!("[{}]({})", text, markdown_destination(&href, &title)).as_str(),
+                        );
+                    }
+                    // Links to a part of the page don't need a reference
+                    (Some(href), Layout::Text) if !href.starts_with('#') => {
+                        let number = match self.references.iter().position(|url| url == &href) {
+                            Some(index) => index + 1,
+                            None => {
+                                self.references.push(href);
+                                self.references.len()
+                            }
+                        };
+                        self.append_inline(format!("{}[{}]", content, number).as_str());
+                    }
+                    _ => self.append_inline(content.as_str()),
+                }
+            }
+            LayoutElement::Image { src, alt, title } => {
+                self.stack.pop();
+                match (src, self.layout) {
+                    (Some(src), Layout::Markdown) => self.append_inline(
+                        format!(
+                            "![{}]({})",
+                            escape_markdown(alt.as_str(), is_in_table_cell),
+                            markdown_destination(&src, &title)
+                        )
+                        .as_str(),
+                    ),
+                    (_, Layout::Text) if !alt.trim().is_empty() => {
+                        self.append_inline(format!("[{}]", alt.trim()).as_str())
+                    }
+                    _ => (),
+                }
+            }
+            LayoutElement::LineBreak => {
+                self.stack.pop();
+                self.parent().is_line_break_pending = true;
+            }
+            LayoutElement::ThematicBreak => {
+                self.stack.pop();
+                let ruler = match self.content_width() {
+                    Some(width) => "-".repeat(width),
+                    None => "---".to_string(),
+                };
+                self.push_block(ruler, false);
+            }
+            LayoutElement::List { .. } => {
+                let items = self.join_blocks();
+                self.stack.pop();
+                self.push_block(items, true);
+            }
+            LayoutElement::ListItem { marker } => {
+                let content = self.join_blocks();
+                self.stack.pop();
+                // The content of an item is aligned with the content of its first line
+                let item = content
+                    .lines()
+                    .enumerate()
+                    .map(|(index, line)| match index {
+                        0 => marker.to_owned() + line,
+                        _ if line.is_empty() => String::new(),
+                        _ => " ".repeat(marker.len()) + line,
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                self.push_block(
+                    match item.is_empty() {
+                        true => marker.trim_end().to_string(),
+                        false => item,
+                    },
+                    false,
+                );
+            }
+            LayoutElement::Blockquote => {
+                let content = self.join_blocks();
+                self.stack.pop();
+                let (prefix, empty_line_prefix) = match self.layout {
+                    Layout::Markdown => ("> ", ">"),
+                    Layout::Text => ("  ", ""),
+                };
+                self.push_block(
+                    content
+                        .lines()
+                        .map(|line| match line {
+                            "" => empty_line_prefix.to_string(),
+                            line => prefix.to_string() + line,
+                        })
+                        .collect::<Vec<String>>()
+                        .join("\n"),
+                    false,
+                );
+            }
+            LayoutElement::Table => {
+                let frame = self.stack.pop().unwrap();
+                let table = match self.layout {
+                    Layout::Markdown => markdown_table(&frame.rows),
+                    Layout::Text => text_table(&frame.rows),
+                };
+                self.push_block(table, false);
+            }
+            LayoutElement::TableSection { .. } => {
+                self.stack.pop();
+            }
+            LayoutElement::TableRow => {
+                let frame = self.stack.pop().unwrap();
+                let is_head = self
+                    .stack
+                    .iter()
+                    .any(|frame| frame.element == LayoutElement::TableSection { is_head: true });
+                let is_header_row = is_head
+                    || (!frame.cells.is_empty()
+                        && frame.cells.iter().all(|(_, is_header)| *is_header));
+                let cells = frame.cells.into_iter().map(|(cell, _)| cell).collect();
+                if let Some(table) = self.find_frame(|element| *element == LayoutElement::Table) {
+                    table.rows.push((cells, is_header_row));
+                }
+            }
+            LayoutElement::TableCell { is_header, colspan } => {
+                // A cell holds a single line, in markdown line breaks are kept with html
+                let line_break = match self.layout {
+                    Layout::Markdown => "<br>",
+                    Layout::Text => " ",
+                };
+                let content = self
+                    .parent()
+                    .join_inline()
+                    .replace("\\\n", line_break)
+                    .replace('\n', " ");
+                self.stack.pop();
+                if let Some(row) = self.find_frame(|element| *element == LayoutElement::TableRow) {
+                    row.cells.push((content.trim().to_string(), is_header));
+                    // A spanned cell is followed by empty cells to keep the columns aligned
+                    for _ in 1..colspan {
+                        row.cells.push((String::new(), is_header));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'b> Serializer for LayoutSerializer<'b> {
+    fn start_elem<'a, AttrIter>(&mut self, name: QualName, attrs: AttrIter) -> io::Result<()>
+    where
+        AttrIter: Iterator<Item = AttrRef<'a>>,
+    {
+        let attrs = attrs
+            .map(|(name, value)| (name.local.to_string(), value.to_string()))
+            .collect::<HashMap<String, String>>();
+        let attribute = |name: &str| attrs.get(name).map(|value| value.to_owned());
+
+        let element = if self.is_ignoring() {
+            LayoutElement::Ignored
+        } else if matches!(self.parent().element, LayoutElement::Preformatted { .. }) {
+            // The language of a code block is given by the class of its code element (e.g. language-rust)
+            if name.local == local_name!("code") {
+                let language = attribute("class").and_then(|class| {
+                    class.split_whitespace().find_map(|class| {
+                        class
+                            .strip_prefix("language-")
+                            .or_else(|| class.strip_prefix("lang-"))
+                            .map(|language| language.to_string())
+                    })
+                });
+                if let LayoutElement::Preformatted { language: current } =
+                    &mut self.parent().element
+                {
+                    if current.is_none() {
+                        *current = language;
+                    }
+                }
+            }
+            LayoutElement::Inline
+        } else if self.is_literal() || name.ns == ns!(svg) {
+            LayoutElement::Inline
+        } else {
+            match name.local {
+                local_name!("h1") => LayoutElement::Heading(1),
+                local_name!("h2") => LayoutElement::Heading(2),
+                local_name!("h3") => LayoutElement::Heading(3),
+                local_name!("h4") => LayoutElement::Heading(4),
+                local_name!("h5") => LayoutElement::Heading(5),
+                local_name!("h6") => LayoutElement::Heading(6),
+                local_name!("em") | local_name!("i") => LayoutElement::Emphasis("*"),
+                local_name!("strong") | local_name!("b") => LayoutElement::Emphasis("**"),
+                local_name!("del") | local_name!("s") | local_name!("strike") => {
+                    LayoutElement::Emphasis("~~")
+                }
+                local_name!("code") | local_name!("kbd") | local_name!("samp") => {
+                    LayoutElement::Code
+                }
+                local_name!("pre") => LayoutElement::Preformatted { language: None },
+                local_name!("a") => LayoutElement::Link {
+                    href: self.resolve_url("href", attribute("href")),
+                    title: attribute("title"),
+                },
+                local_name!("img") => LayoutElement::Image {
+                    src: self.resolve_url("src", attribute("src")),
+                    alt: attribute("alt").unwrap_or_default(),
+                    title: attribute("title"),
+                },
+                local_name!("br") => LayoutElement::LineBreak,
+                local_name!("hr") => LayoutElement::ThematicBreak,
+                local_name!("ul") | local_name!("menu") => LayoutElement::List {
+                    is_ordered: false,
+                    number: 1,
+                },
+                local_name!("ol") => LayoutElement::List {
+                    is_ordered: true,
+                    number: attribute("start")
+                        .and_then(|start| start.trim().parse().ok())
+                        .unwrap_or(1),
+                },
+                local_name!("li") => {
+                    let bullet = match self.layout {
+                        Layout::Markdown => "- ",
+                        Layout::Text => "* ",
+                    };
+                    let marker = match &mut self.parent().element {
+                        LayoutElement::List {
+                            is_ordered: true,
+                            number,
+                        } => {
+                            *number += 1;
+                            format!("{}. ", *number - 1)
+                        }
+                        _ => bullet.to_string(),
+                    };
+                    LayoutElement::ListItem { marker }
+                }
+                local_name!("blockquote") => LayoutElement::Blockquote,
+                local_name!("table") => LayoutElement::Table,
+                local_name!("thead") => LayoutElement::TableSection { is_head: true },
+                local_name!("tbody") | local_name!("tfoot") => {
+                    LayoutElement::TableSection { is_head: false }
+                }
+                local_name!("tr") => LayoutElement::TableRow,
+                local_name!("td") | local_name!("th") => LayoutElement::TableCell {
+                    is_header: name.local == local_name!("th"),
+                    colspan: attribute("colspan")
+                        .and_then(|colspan| colspan.trim().parse().ok())
+                        .unwrap_or(1)
+                        .clamp(1, 1000),
+                },
+                local_name!("head")
+                | local_name!("script")
+                | local_name!("style")
+                | local_name!("template")
+                | local_name!("noscript")
+                | local_name!("title")
+                | local_name!("meta")
+                | local_name!("link")
+                | local_name!("base") => LayoutElement::Ignored,
+                _ if is_block_element(&name) => LayoutElement::Block,
+                _ => LayoutElement::Inline,
+            }
+        };
+
+        self.stack.push(LayoutFrame::new(element));
+        Ok(())
+    }
+
+    fn end_elem(&mut self, _name: QualName) -> io::Result<()> {
+        if self.stack.len() > 1 {
+            let element = self.parent().element.to_owned();
+            self.close_element(element);
+        }
+        Ok(())
+    }
+
+    fn write_text(&mut self, text: &str) -> io::Result<()> {
+        if self.is_ignoring() {
+            return Ok(());
+        }
+        if self.is_literal() {
+            self.parent().inline.push_str(text);
+            return Ok(());
+        }
+
+        let text = collapse_whitespaces(text);
+        let text = match self.layout {
+            Layout::Markdown => escape_markdown(text.as_str(), self.is_in_table_cell()),
+            Layout::Text => text,
+        };
+        self.append_inline(text.as_str());
+        Ok(())
+    }
+
+    fn write_comment(&mut self, _text: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_doctype(&mut self, _name: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_processing_instruction(&mut self, _target: &str, _data: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// A gfm table needs a header row, it is left empty when the table has no header
+fn markdown_table(rows: &[(Vec<String>, bool)]) -> String {
+    let width = rows.iter().map(|(cells, _)| cells.len()).max().unwrap_or(0);
+    if width == 0 {
+        return String::new();
+    }
+    let (header, rows) = match rows.split_first() {
+        Some(((cells, true), rows)) => (cells.to_owned(), rows),
+        _ => (vec![], rows),
+    };
+
+    let format_row = |cells: &[String]| {
+        let mut cells = cells.to_vec();
+        cells.resize(width, String::new());
+        format!("| {} |", cells.join(" | "))
+    };
+
+    std::iter::once(format_row(&header))
+        .chain(std::iter::once(format_row(&vec!["---".to_string(); width])))
+        .chain(rows.iter().map(|(cells, _)| format_row(cells)))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// Columns are aligned and separated with two spaces, header rows are underlined
+fn text_table(rows: &[(Vec<String>, bool)]) -> String {
+    let mut widths: Vec<usize> = vec![];
+    for (cells, _) in rows {
+        for (column, cell) in cells.iter().enumerate() {
+            let width = cell.chars().count();
+            match widths.get_mut(column) {
+                Some(current) => *current = (*current).max(width),
+                None => widths.push(width),
+            }
+        }
+    }
+
+    let format_row = |cells: Vec<String>| {
+        widths
+            .iter()
+            .enumerate()
+            .map(|(column, width)| {
+                let cell = cells.get(column).map_or("", |cell| cell.as_str());
+                format!("{}{}", cell, " ".repeat(width - cell.chars().count()))
+            })
+            .collect::<Vec<String>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    let mut lines = vec![];
+    for (index, (cells, is_header)) in rows.iter().enumerate() {
+        lines.push(format_row(cells.to_owned()));
+        let is_last_header_row =
+            *is_header && !rows.get(index + 1).is_some_and(|(_, is_header)| *is_header);
+        if is_last_header_row {
+            lines.push(format_row(
+                widths.iter().map(|width| "-".repeat(*width)).collect(),
+            ));
+        }
+    }
+    lines.join("\n")
+}
+
+// Greedy word wrapping, a word longer than the width is kept whole on its own line
+fn wrap_text(text: &str, width: usize) -> String {
+    text.lines()
+        .map(|line| {
+            let mut lines = vec![];
+            let mut current = String::new();
+            for word in line.split(' ').filter(|word| !word.is_empty()) {
+                if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > width
+                {
+                    lines.push(std::mem::take(&mut current));
+                }
+                if !current.is_empty() {
+                    current.push(' ');
+                }
+                current.push_str(word);
+            }
+            lines.push(current);
+            lines.join("\n")
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// The destination of a link or an image, followed by its title when it has one
+fn markdown_destination(url: &str, title: &Option<String>) -> String {
+    let url = if url.contains([' ', '(', ')', '<', '>']) {
+        format!("<{}>", url.replace('<', "%3C").replace('>', "%3E"))
+    } else {
+        url.to_string()
+    };
+    match title {
+        Some(title) => format!("{} \"{}\"", url, title.replace('"', "\\\"")),
+        None => url,
+    }
+}
+
+fn collapse_whitespaces(text: &str) -> String {
+    let mut collapsed = String::new();
+    for (index, part) in text.split(|c: char| c.is_ascii_whitespace()).enumerate() {
+        if index > 0 && !collapsed.ends_with(' ') {
+            collapsed.push(' ');
+        }
+        collapsed.push_str(part);
+    }
+    collapsed
+}
+
+// Characters having a meaning in markdown are escaped, pipes are only escaped in table cells
+fn escape_markdown(text: &str, is_in_table_cell: bool) -> String {
+    text.chars().fold(String::new(), |mut acc, c| {
+        match c {
+            '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '~' => acc.push('\\'),
+            '|' if is_in_table_cell => acc.push('\\'),
+            _ => (),
+        }
+        acc.push(c);
+        acc
+    })
+}
+
+// A paragraph starting like a heading, a blockquote, a list item or a thematic break is escaped
+fn escape_markdown_line_start(text: &str) -> String {
+    static LINE_START: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"^(?:(#{1,6}|>|[-+=])(?:\s|$)|(\d{1,9})([.)])(?:\s|$))").unwrap()
+    });
+    match LINE_START.captures(text) {
+        Some(captures) => match (captures.get(1), captures.get(2)) {
+            (Some(_), _) => format!("\\{}", text),
+            (_, Some(number)) => format!("{}\\{}", number.as_str(), &text[number.end()..]),
+            _ => text.to_string(),
+        },
+        None => text.to_string(),
+    }
+}
+
+fn longest_backtick_run(text: &str) -> usize {
+    text.split(|c| c != '`')
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0)
+}
@@ -1,14 +1,50 @@
-use colored::*;
 use html5ever::{
-    serialize::{AttrRef, Serialize, Serializer, TraversalScope},
-    QualName,
+    serialize::{AttrRef, Serializer, TraversalScope},
+    tendril::StrTendril,
+    Attribute, QualName,
 };
-use markup5ever::LocalName;
-use markup5ever::{local_name, namespace_url, ns};
-use markup5ever_rcdom::{Node, SerializableHandle};
-use regex::{Regex, RegexBuilder};
-use serde;
-use std::{collections::HashMap, io, rc::Rc};
+use indexmap::IndexMap;
+use markup5ever::{local_name, namespace_prefix, namespace_url, ns};
+use markup5ever::{LocalName, Namespace, Prefix};
+use markup5ever_rcdom::{Handle, Node, NodeData};
+use regex::Regex;
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::HashMap,
+    io::{self, Write},
+    rc::Rc,
+    sync::LazyLock,
+};
+use url::Url;
+
+use crate::tree;
+use layout::{serialize_node_to_layout, Layout};
+use theme::{Colorizer, Style, Theme};
+
+mod layout;
+pub mod theme;
+
+// The regular expressions are compiled once, they are used for every node
+static WHITESPACES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s+").unwrap());
+// Unlike \s, no-break spaces aren't collapsed by browsers
+static HTML_WHITESPACES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[ \t\n\x0C\r]+").unwrap());
+
+// Attributes holding an url, their value is resolved against the base url of the document when one is known
+pub const URL_ATTRIBUTES: [&str; 12] = [
+    "action",
+    "background",
+    "cite",
+    "codebase",
+    "data",
+    "formaction",
+    "href",
+    "icon",
+    "longdesc",
+    "manifest",
+    "poster",
+    "src",
+];
 
 // Represents an HTML tree transformed to a JSON tree
 #[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug)]
@@ -19,9 +55,16 @@ enum SNode {
         #[serde(rename(serialize = "type", deserialize = "type"))]
         kind: String,
         text: Option<String>,
-        attributes: Option<HashMap<String, Option<String>>>,
+        // The attributes keep the order of the document so the html rendered back from JSON is the same
+        attributes: Option<IndexMap<String, Option<String>>>,
         children: Option<Vec<SNode>>,
     },
+    // Only rendered in lossless mode, it comes before comments which have the same properties
+    Text {
+        #[serde(rename(serialize = "type", deserialize = "type"))]
+        kind: TextKind,
+        text: String,
+    },
     Comment {
         #[serde(rename(serialize = "type", deserialize = "type"))]
         kind: String,
@@ -40,14 +83,120 @@ enum SNode {
     },
 }
 
+// Represents a JSON tree written on its own line, the selector and the source are only defined when requested
+#[derive(serde::Serialize)]
+struct AnnotatedNode<'a, T: serde::Serialize> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    selector: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<&'a str>,
+    #[serde(flatten)]
+    node: T,
+}
+
+// The shape of the JSON documents
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum JsonFormat {
+    // Every node has a type, a text, attributes and children, missing data is set to null
+    #[default]
+    Rup,
+    // The shape of pup (https://github.com/ericchiang/pup): a tag property, attributes flattened onto the node
+    // and the texts and comments of the children concatenated in a text and a comment property
+    Pup,
+    // Like rup but texts are children nodes of type text kept as is, the order of texts and elements is kept
+    // so the html could be rendered back from the JSON
+    Lossless,
+}
+
+// The type of a text node, a dedicated type allows to tell texts and comments apart when reading JSON
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug)]
+enum TextKind {
+    #[serde(rename = "text")]
+    Text,
+}
+
+// How the attributes extracted from every node are rendered
+#[derive(Clone, Copy, PartialEq)]
+pub enum AttributesFormat {
+    // A JSON object written on its own line, a missing attribute is set to null
+    Json,
+    // A row of tab-separated values following a header row, a missing attribute is left empty
+    Tsv,
+}
+
+// Which texts are extracted with --keep-text-only
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum TextMode {
+    // The texts of the node and of its descendants, except the content of script and style elements
+    #[default]
+    All,
+    // The texts which are direct children of the node
+    Own,
+    // Like the innerText property of browsers: hidden content (template, noscript, hidden attribute,
+    // display:none inline style) is left out and block elements start on a new line
+    Inner,
+}
+
+// A part of a format string, every placeholder is replaced with data of the matched node
+#[derive(Clone, PartialEq, Debug)]
+enum FormatSegment {
+    Literal(String),
+    // {name}: the tag name of the element
+    Name,
+    // {attr:NAME}: the value of an attribute, empty when the element doesn't have it
+    Attribute(String),
+    // {text}: the text extracted like with --keep-text-only
+    Text,
+    // {inner_html}: the markup of the children
+    InnerHtml,
+    // {outer_html}: the markup of the node itself
+    OuterHtml,
+    // {index}: the position of the node among the matched nodes, starting from 1
+    Index,
+    // {source}: the file or the url the node was read from
+    Source,
+    // {selector}: the css expression that matched the node
+    Selector,
+}
+
+// Describes how to print every matched node on its own line, e.g. "{attr:href}\t{text}"
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct FormatString {
+    segments: Vec<FormatSegment>,
+}
+
 #[derive(Clone, Default)]
 pub struct SerializeSettings {
     is_color_enabled: bool,
+    theme: Theme,
     should_render_text_only: bool,
     should_render_attributes: bool,
     attributes: Vec<String>,
+    attributes_format: Option<AttributesFormat>,
     is_json_enabled: bool,
+    json_format: JsonFormat,
+    is_xml_enabled: bool,
+    base_url: Option<Url>,
+    indent: Option<usize>,
+    is_raw_enabled: bool,
+    is_minify_enabled: bool,
+    should_keep_comments: bool,
+    is_ndjson_enabled: bool,
+    is_yaml_enabled: bool,
+    is_toml_enabled: bool,
+    is_markdown_enabled: bool,
+    text_mode: TextMode,
+    is_text_node_per_line: bool,
+    is_text_enabled: bool,
+    width: Option<usize>,
+    should_annotate_selector: bool,
+    selector: Option<String>,
+    source: Option<String>,
+    format_string: Option<FormatString>,
+    first_index: usize,
 }
+
+#[derive(Clone)]
 pub struct SerializeSettingsBuilder {
     serialize_settings: SerializeSettings,
 }
@@ -63,64 +212,653 @@ impl SerializeSettingsBuilder {
         self.serialize_settings.is_color_enabled = true;
     }
 
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.serialize_settings.theme = theme;
+    }
+
     pub fn should_render_text_only(&mut self) {
         self.serialize_settings.should_render_text_only = true;
     }
 
+    pub fn set_text_mode(&mut self, text_mode: TextMode) {
+        self.serialize_settings.text_mode = text_mode;
+    }
+
+    // Extract every text node on its own line instead of joining the texts with a space
+    pub fn render_text_node_per_line(&mut self) {
+        self.serialize_settings.is_text_node_per_line = true;
+    }
+
     pub fn should_render_attributes(&mut self, attributes: Vec<String>) {
         self.serialize_settings.should_render_attributes = true;
         self.serialize_settings.attributes = attributes;
     }
 
+    // Render the extracted attributes as records having a column per attribute instead of joining the values
+    pub fn set_attributes_format(&mut self, attributes_format: AttributesFormat) {
+        self.serialize_settings.attributes_format = Some(attributes_format);
+    }
+
     pub fn render_json(&mut self) {
         self.serialize_settings.is_json_enabled = true;
     }
 
-    fn build(&mut self) -> SerializeSettings {
-        self.serialize_settings.to_owned()
+    pub fn set_json_format(&mut self, json_format: JsonFormat) {
+        self.serialize_settings.json_format = json_format;
+    }
+
+    // Render well-formed xml: empty elements are self-closed, text containing markup is wrapped in a CDATA section
+    // and names keep their namespace prefix
+    pub fn render_xml(&mut self) {
+        self.serialize_settings.is_xml_enabled = true;
+    }
+
+    // The base url is the address of the document, a <base href> element found in the document takes precedence
+    pub fn set_base_url(&mut self, base_url: Url) {
+        self.serialize_settings.base_url = Some(base_url);
+    }
+
+    // Render every block-level element on its own line, indented by the given number of spaces per level
+    pub fn set_indent(&mut self, indent: usize) {
+        self.serialize_settings.indent = Some(indent);
+    }
+
+    // Render the markup as found in the document: whitespaces are kept and urls are not resolved
+    pub fn render_raw(&mut self) {
+        self.serialize_settings.is_raw_enabled = true;
+    }
+
+    // Render the markup as small as a browser parses it the same: whitespaces are collapsed where they are
+    // not significant, comments are dropped, optional end tags and attribute quotes are omitted
+    pub fn render_minified(&mut self) {
+        self.serialize_settings.is_minify_enabled = true;
+    }
+
+    // Keep the comments of the minified markup
+    pub fn keep_comments(&mut self) {
+        self.serialize_settings.should_keep_comments = true;
+    }
+
+    // Render every node as a JSON object written on its own line instead of a JSON array
+    pub fn render_ndjson(&mut self) {
+        self.serialize_settings.is_ndjson_enabled = true;
+    }
+
+    // Render the nodes, or the extracted texts and attributes, as a yaml sequence
+    pub fn render_yaml(&mut self) {
+        self.serialize_settings.is_yaml_enabled = true;
+    }
+
+    // Same as yaml, a toml document being a table the sequence is the value of a nodes, texts or attributes key
+    pub fn render_toml(&mut self) {
+        self.serialize_settings.is_toml_enabled = true;
+    }
+
+    // Render the nodes as markdown (CommonMark with GitHub flavored tables and strikethrough)
+    pub fn render_markdown(&mut self) {
+        self.serialize_settings.is_markdown_enabled = true;
+    }
+
+    // Render the nodes as plain text laid out like a text browser does, paragraphs are wrapped to the width
+    pub fn render_text(&mut self) {
+        self.serialize_settings.is_text_enabled = true;
+    }
+
+    // The number of columns text is wrapped to, 80 by default
+    pub fn set_width(&mut self, width: usize) {
+        self.serialize_settings.width = Some(width);
+    }
+
+    // Add the selector that matched to every JSON object, the selector is given with set_selector
+    pub fn annotate_selector(&mut self) {
+        self.serialize_settings.should_annotate_selector = true;
+    }
+
+    pub fn set_selector(&mut self, selector: String) {
+        self.serialize_settings.selector = Some(selector);
+    }
+
+    // Add the file or the url the nodes were read from to every JSON object
+    pub fn annotate_source(&mut self, source: String) {
+        self.serialize_settings.source = Some(source);
+    }
+
+    // Print every node with a format string instead of rendering it
+    pub fn set_format_string(&mut self, format_string: FormatString) {
+        self.serialize_settings.format_string = Some(format_string);
+    }
+
+    // The index of the first node given to serialize_nodes, when the nodes are rendered in several calls
+    pub fn set_first_index(&mut self, first_index: usize) {
+        self.serialize_settings.first_index = first_index;
+    }
+
+    pub fn build(&mut self) -> SerializeSettings {
+        let mut settings = self.serialize_settings.to_owned();
+        // Raw markup is written as is, so its urls are not resolved
+        if settings.is_raw_enabled {
+            settings.base_url = None;
+        }
+        settings
     }
 }
 
-// Output a node list as a list of html markup or JSON strings
+// Output a node list as a list of html markup or JSON strings, the tests compare it to the expected output
+#[cfg(test)]
 pub fn serialize_nodes(
-    mut settings_builder: SerializeSettingsBuilder,
+    settings_builder: SerializeSettingsBuilder,
     nodes: Vec<Rc<Node>>,
 ) -> io::Result<String> {
+    let mut buffer = vec![];
+    write_nodes(settings_builder, nodes, &mut buffer)?;
+    String::from_utf8(buffer).map_err(io::Error::other)
+}
+
+// Same as serialize_nodes but every node is written to the output as soon as it is rendered, so a single
+// rendered node is held in memory at a time. Yaml, toml and pup JSON documents are still rendered as a whole.
+// Returns whether something was written
+pub fn write_nodes(
+    mut settings_builder: SerializeSettingsBuilder,
+    nodes: Vec<Rc<Node>>,
+    output: &mut dyn Write,
+) -> io::Result<bool> {
+    let settings = settings_builder.build();
+    let colorizer = Colorizer::new(settings.is_color_enabled, settings.theme);
+
+    if let Some(format_string) = settings.format_string.to_owned() {
+        write_records(
+            output,
+            "\n",
+            nodes.iter().enumerate().map(|(index, node)| {
+                serialize_node_to_format_string(&settings, &format_string, index, node).map(Some)
+            }),
+        )
+    } else if let (true, Some(attributes_format)) = (
+        settings.should_render_attributes,
+        settings.attributes_format,
+    ) {
+        write_attribute_records(&settings, attributes_format, colorizer, &nodes, output)
+    } else if settings.is_markdown_enabled {
+        write_records(
+            output,
+            "\n\n",
+            nodes
+                .iter()
+                .map(|node| serialize_node_to_layout(&settings, Layout::Markdown, node)),
+        )
+    } else if settings.is_text_enabled {
+        write_records(
+            output,
+            "\n\n",
+            nodes
+                .iter()
+                .map(|node| serialize_node_to_layout(&settings, Layout::Text, node)),
+        )
+    } else if settings.is_yaml_enabled || settings.is_toml_enabled {
+        write_records(
+            output,
+            "",
+            std::iter::once(serialize_nodes_to_data_format(&settings, &nodes).map(Some)),
+        )
+    } else if settings.is_ndjson_enabled {
+        write_records(
+            output,
+            "\n",
+            nodes.iter().map(|node| {
+                serialize_node_to_ndjson(&settings, node)
+                    .map(|json| json.map(|json| colorizer.colorize_json(&json)))
+            }),
+        )
+    } else if settings.is_json_enabled && settings.json_format == JsonFormat::Pup {
+        write_records(
+            output,
+            "",
+            std::iter::once(
+                serialize_nodes_to_pup_json(&settings, &nodes)
+                    .map(|json| Some(colorizer.colorize_json(&json))),
+            ),
+        )
+    } else if settings.is_json_enabled {
+        // The array is written an element at a time, a node which can't be rendered in JSON is null
+        output.write_all(colorizer.colorize("[", Style::JsonPunctuation).as_bytes())?;
+        write_records(
+            output,
+            colorizer.colorize(",", Style::JsonPunctuation).as_str(),
+            nodes.iter().map(|node| {
+                serde_json::to_string(&convert_node_to_snode(&settings, node))
+                    .map(|json| Some(colorizer.colorize_json(&json)))
+                    .map_err(io::Error::other)
+            }),
+        )?;
+        output.write_all(colorizer.colorize("]", Style::JsonPunctuation).as_bytes())?;
+        Ok(true)
+    } else {
+        // A new line is added to separate every node to be processed easily in a pipe
+        let mut is_written = false;
+        for node in nodes.iter() {
+            let separator = if is_written { "\n" } else { "" };
+            is_written |= write_markup(
+                &settings,
+                node,
+                TraversalScope::IncludeNode,
+                separator,
+                output,
+            )?;
+        }
+        Ok(is_written)
+    }
+}
+
+// Writes every rendered node followed by the separator except the last one, a node rendering nothing is skipped.
+// Returns whether something was written
+fn write_records(
+    output: &mut dyn Write,
+    separator: &str,
+    records: impl Iterator<Item = io::Result<Option<String>>>,
+) -> io::Result<bool> {
+    let mut is_empty = true;
+    for record in records {
+        if let Some(record) = record? {
+            if !is_empty {
+                output.write_all(separator.as_bytes())?;
+            }
+            output.write_all(record.as_bytes())?;
+            is_empty = false;
+        }
+    }
+    Ok(!is_empty)
+}
+
+// Colors a JSON document rendered elsewhere (e.g. tables and templates) like the JSON rendered from nodes
+pub fn colorize_json(mut settings_builder: SerializeSettingsBuilder, json: &str) -> String {
     let settings = settings_builder.build();
+    Colorizer::new(settings.is_color_enabled, settings.theme).colorize_json(json)
+}
+
+// Output the value of an attribute of the node, urls are resolved like when rendering the node
+pub fn serialize_attribute(
+    settings: &SerializeSettings,
+    node: &Rc<Node>,
+    name: &str,
+) -> Option<String> {
+    let value = match &node.data {
+        NodeData::Element { attrs, .. } => attrs
+            .borrow()
+            .iter()
+            .find(|attr| attr.name.local.as_ref() == name)
+            .map(|attr| attr.value.to_string())?,
+        _ => return None,
+    };
+
+    Some(resolve_url(&settings.base_url, name, value.trim()))
+}
+
+// Text is wrapped to at least a column, every word is then written on its own line
+pub fn parse_width(width: &str) -> Result<usize, String> {
+    match width.parse::<usize>() {
+        Ok(0) => Err("the width must be at least 1 column".to_string()),
+        width => width.map_err(|e| e.to_string()),
+    }
+}
+
+// Placeholders are written between braces, a brace is written twice to be kept as is
+// and \t, \n and \\ are replaced with a tab, a new line and a backslash
+pub fn parse_format_string(format: &str) -> Result<FormatString, String> {
+    let mut segments = vec![];
+    let mut literal = String::new();
+    let mut chars = format.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.peek() {
+                Some('t') => literal.push('\t'),
+                Some('n') => literal.push('\n'),
+                Some('r') => literal.push('\r'),
+                Some('\\') => literal.push('\\'),
+                // An unknown escape sequence is kept as is
+                _ => {
+                    literal.push('\\');
+                    continue;
+                }
+            },
+            '{' if chars.peek() == Some(&'{') => literal.push('{'),
+            '}' if chars.peek() == Some(&'}') => literal.push('}'),
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return Err(format!("unclosed placeholder {{{}", placeholder)),
+                    }
+                }
+                if !literal.is_empty() {
+                    segments.push(FormatSegment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(parse_placeholder(placeholder.trim())?);
+                continue;
+            }
+            '}' => return Err("unexpected }, write }} to print a brace".to_string()),
+            c => {
+                literal.push(c);
+                continue;
+            }
+        }
+        // The second character of an escape sequence or of a doubled brace
+        chars.next();
+    }
+    if !literal.is_empty() {
+        segments.push(FormatSegment::Literal(literal));
+    }
+
+    Ok(FormatString { segments })
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<FormatSegment, String> {
+    Ok(match placeholder {
+        "name" => FormatSegment::Name,
+        "text" => FormatSegment::Text,
+        "inner_html" => FormatSegment::InnerHtml,
+        "outer_html" => FormatSegment::OuterHtml,
+        "index" => FormatSegment::Index,
+        "source" => FormatSegment::Source,
+        "selector" => FormatSegment::Selector,
+        _ => match placeholder.strip_prefix("attr:") {
+            Some(name) if !name.trim().is_empty() => {
+                FormatSegment::Attribute(name.trim().to_string())
+            }
+            _ => return Err(format!("unknown placeholder {{{}}}", placeholder)),
+        },
+    })
+}
+
+// Reads nodes rendered in JSON, either JSON arrays or JSON objects written on their own line,
+// and returns a document holding them so they could be filtered and rendered again.
+// Texts are only kept at their place with the lossless format, otherwise the text of a node comes first
+pub fn deserialize_nodes(content: &[u8]) -> io::Result<Handle> {
+    let document = Node::new(NodeData::Document);
+
+    for value in serde_json::Deserializer::from_slice(content).into_iter::<serde_json::Value>() {
+        let snodes = match value? {
+            serde_json::Value::Array(values) => values
+                .into_iter()
+                // A null stands for a node that can't be rendered in JSON
+                .filter(|value| !value.is_null())
+                .map(serde_json::from_value)
+                .collect::<Result<Vec<SNode>, _>>()?,
+            value => vec![serde_json::from_value(value)?],
+        };
+        for snode in snodes {
+            tree::append(&document, convert_snode_to_node(snode));
+        }
+    }
+
+    Ok(document)
+}
+
+fn convert_snode_to_node(snode: SNode) -> Handle {
+    match snode {
+        SNode::Regular {
+            name,
+            text,
+            attributes,
+            children,
+            ..
+        } => {
+            let node = Node::new(NodeData::Element {
+                name: QualName::new(None, ns!(html), LocalName::from(name)),
+                attrs: RefCell::new(
+                    attributes
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|(name, value)| Attribute {
+                            name: QualName::new(None, ns!(), LocalName::from(name)),
+                            value: StrTendril::from(value.unwrap_or_default()),
+                        })
+                        .collect(),
+                ),
+                template_contents: None,
+                mathml_annotation_xml_integration_point: false,
+            });
+            if let Some(text) = text {
+                tree::append(
+                    &node,
+                    Node::new(NodeData::Text {
+                        contents: RefCell::new(StrTendril::from(text)),
+                    }),
+                );
+            }
+            for child in children.unwrap_or_default() {
+                tree::append(&node, convert_snode_to_node(child));
+            }
+            node
+        }
+        SNode::Text { text, .. } => Node::new(NodeData::Text {
+            contents: RefCell::new(StrTendril::from(text)),
+        }),
+        SNode::Comment { text, .. } => Node::new(NodeData::Comment {
+            contents: StrTendril::from(text.unwrap_or_default()),
+        }),
+        SNode::DocType { name, .. } => Node::new(NodeData::Doctype {
+            name: StrTendril::from(name),
+            public_id: StrTendril::new(),
+            system_id: StrTendril::new(),
+        }),
+        SNode::ProcessingInstruction { name, text, .. } => {
+            Node::new(NodeData::ProcessingInstruction {
+                target: StrTendril::from(name),
+                contents: StrTendril::from(text.unwrap_or_default()),
+            })
+        }
+    }
+}
+
+// Computes the base url of a document, the first <base href> element of the document is resolved
+// against the provided url. The whole document is searched, so it is computed once per document
+pub fn find_base_url(url: Option<Url>, document: &Handle) -> Option<Url> {
+    match find_base_href(document) {
+        Some(href) => match &url {
+            Some(url) => url.join(href.as_str()).ok(),
+            None => Url::parse(href.as_str()).ok(),
+        }
+        .or(url),
+        None => url,
+    }
+}
+
+fn find_base_href(node: &Rc<Node>) -> Option<String> {
+    if let NodeData::Element { name, attrs, .. } = &node.data {
+        if name.local == local_name!("base") {
+            if let Some(attr) = attrs
+                .borrow()
+                .iter()
+                .find(|attr| attr.name.local == local_name!("href"))
+            {
+                return Some(attr.value.to_string());
+            }
+        }
+    }
+
+    node.children.borrow().iter().find_map(find_base_href)
+}
+
+// Resolves an attribute value against the base url when the attribute holds an url
+fn resolve_url(base_url: &Option<Url>, name: &str, value: &str) -> String {
+    let join = |base_url: &Url, value: &str| {
+        base_url
+            .join(value)
+            .map_or_else(|_| value.to_string(), |v| v.to_string())
+    };
+
+    match base_url {
+        // A srcset is a list of comma separated candidates made of an url and an optional descriptor
+        Some(base_url) if name == "srcset" => value
+            .split(',')
+            .map(
+                |candidate| match candidate.trim().split_once(char::is_whitespace) {
+                    Some((url, descriptor)) => join(base_url, url) + " " + descriptor.trim(),
+                    None => join(base_url, candidate.trim()),
+                },
+            )
+            .collect::<Vec<String>>()
+            .join(", "),
+        Some(base_url) if URL_ATTRIBUTES.contains(&name) => join(base_url, value),
+        _ => value.to_string(),
+    }
+}
+
+// Like pup, the JSON document is indented with a single space
+fn serialize_nodes_to_pup_json(
+    settings: &SerializeSettings,
+    nodes: &[Rc<Node>],
+) -> io::Result<String> {
+    let value = serde_json::Value::Array(
+        nodes
+            .iter()
+            .flat_map(|node| convert_node_to_pup_value(settings, node))
+            .collect(),
+    );
+
+    let mut buffer = vec![];
+    let mut serializer = serde_json::Serializer::with_formatter(
+        &mut buffer,
+        serde_json::ser::PrettyFormatter::with_indent(b" "),
+    );
+    serde::Serialize::serialize(&value, &mut serializer)?;
+    Ok(String::from_utf8_lossy(&buffer).to_string())
+}
+
+// Properties are set in the same order as pup does, so an attribute named tag, text,
+// comment or children is overridden
+fn convert_node_to_pup_value(
+    settings: &SerializeSettings,
+    node: &Rc<Node>,
+) -> Option<serde_json::Value> {
+    let (name, attrs) = match &node.data {
+        NodeData::Element { name, attrs, .. } => (name, attrs),
+        _ => return None,
+    };
+
+    let mut object = serde_json::Map::new();
+    for attr in attrs.borrow().iter() {
+        let value = resolve_url(
+            &settings.base_url,
+            attr.name.local.as_ref(),
+            attr.value.as_ref(),
+        );
+        object.insert(
+            qualified_name(settings, &attr.name),
+            serde_json::Value::String(escape_like_pup(value.as_str())),
+        );
+    }
+    object.insert(
+        "tag".to_string(),
+        serde_json::Value::String(qualified_name(settings, name)),
+    );
+
+    let mut children = vec![];
+    for child in node.children.borrow().iter() {
+        let (key, text) = match &child.data {
+            NodeData::Element { .. } => {
+                children.extend(convert_node_to_pup_value(settings, child));
+                continue;
+            }
+            NodeData::Text { contents } => ("text", contents.borrow().trim().to_string()),
+            NodeData::Comment { contents } => ("comment", contents.trim().to_string()),
+            _ => continue,
+        };
+        if text.is_empty() {
+            continue;
+        }
+
+        // Texts of several children are separated with a space
+        let text = escape_like_pup(text.as_str());
+        let text = match object.get(key).and_then(|v| v.as_str()) {
+            Some(current) => format!("{} {}", current, text),
+            None => text,
+        };
+        object.insert(key.to_string(), serde_json::Value::String(text));
+    }
+    if !children.is_empty() {
+        object.insert("children".to_string(), serde_json::Value::Array(children));
+    }
+
+    // Like pup, which renders Go maps, the keys are sorted
+    let mut entries = object
+        .into_iter()
+        .collect::<Vec<(String, serde_json::Value)>>();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Some(serde_json::Value::Object(entries.into_iter().collect()))
+}
 
-    if settings.is_json_enabled {
-        Ok(serialize_nodes_to_json(&nodes))
+// pup escapes values as the html.EscapeString function of go does
+fn escape_like_pup(text: &str) -> String {
+    text.chars().fold(String::new(), |acc, c| match c {
+        '<' => acc + "&lt;",
+        '>' => acc + "&gt;",
+        '&' => acc + "&amp;",
+        '\'' => acc + "&#39;",
+        '"' => acc + "&#34;",
+        c => acc + c.to_string().as_str(),
+    })
+}
+
+// The node has the shape given by the JSON format, like with --json
+fn serialize_node_to_ndjson(
+    settings: &SerializeSettings,
+    node: &Rc<Node>,
+) -> io::Result<Option<String>> {
+    if settings.json_format == JsonFormat::Pup {
+        convert_node_to_pup_value(settings, node)
+            .map(|node| serialize_annotated_node(settings, node))
+            .transpose()
     } else {
-        serialize_nodes_to_html(settings, &nodes)
+        convert_node_to_snode(settings, node)
+            .map(|node| serialize_annotated_node(settings, node))
+            .transpose()
     }
 }
 
-fn serialize_nodes_to_json(nodes: &Vec<Rc<Node>>) -> String {
-    serde_json::to_string(&nodes.iter().fold(vec![], |mut acc, node| {
-        acc.push(convert_node_to_snode(node));
-        acc
-    }))
-    .unwrap()
+fn serialize_annotated_node<T: serde::Serialize>(
+    settings: &SerializeSettings,
+    node: T,
+) -> io::Result<String> {
+    serde_json::to_string(&AnnotatedNode {
+        selector: settings
+            .selector
+            .as_deref()
+            .filter(|_| settings.should_annotate_selector),
+        source: settings.source.as_deref(),
+        node,
+    })
+    .map_err(io::Error::other)
 }
 
-fn convert_node_to_snode(node: &Rc<Node>) -> Option<SNode> {
+fn convert_node_to_snode(settings: &SerializeSettings, node: &Rc<Node>) -> Option<SNode> {
+    let is_lossless = settings.json_format == JsonFormat::Lossless;
+
     match &node.data {
         markup5ever_rcdom::NodeData::Element { name, attrs, .. } => {
-            let children = node.children.take();
-            let attributes = attrs.take();
+            // The document is rendered again for every selector, so it is left intact
+            let children = node.children.borrow();
+            let attributes = attrs.borrow();
 
             Some(SNode::Regular {
-                name: name.local.trim().to_string(),
+                name: qualified_name(settings, name),
                 attributes: if attributes.is_empty() {
                     None
                 } else {
-                    Some(attributes.iter().fold(HashMap::new(), |mut acc, attr| {
+                    Some(attributes.iter().fold(IndexMap::new(), |mut acc, attr| {
                         acc.insert(
-                            attr.name.local.to_string(),
+                            qualified_name(settings, &attr.name),
                             if attr.value.is_empty() {
                                 None
                             } else {
-                                Some(attr.value.to_string())
+                                Some(resolve_url(
+                                    &settings.base_url,
+                                    attr.name.local.as_ref(),
+                                    attr.value.as_ref(),
+                                ))
                             },
                         );
                         acc
@@ -129,7 +867,7 @@ fn convert_node_to_snode(node: &Rc<Node>) -> Option<SNode> {
                 children: {
                     let cs = children
                         .iter()
-                        .flat_map(|n| convert_node_to_snode(&n))
+                        .flat_map(|n| convert_node_to_snode(settings, n))
                         .collect::<Vec<SNode>>();
 
                     if cs.is_empty() {
@@ -141,20 +879,15 @@ fn convert_node_to_snode(node: &Rc<Node>) -> Option<SNode> {
                 kind: "regular".to_string(),
                 text: children
                     .iter()
+                    .filter(|_| !is_lossless)
                     .flat_map(|n| match n.data {
                         markup5ever_rcdom::NodeData::Text { ref contents } => {
-                            Some(contents.take().to_string())
+                            Some(contents.borrow().to_string())
                         }
                         _ => None,
                     })
-                    .fold(Some(String::new()), |text, e| text.map(|v| v + e.as_ref()))
-                    .map(|v| {
-                        Regex::new(r"\s+")
-                            .unwrap()
-                            .replace_all(v.as_str(), " ")
-                            .trim()
-                            .to_string()
-                    })
+                    .try_fold(String::new(), |text, e| Some(text + e.as_ref()))
+                    .map(|v| WHITESPACES.replace_all(v.as_str(), " ").trim().to_string())
                     .filter(|v| !v.is_empty()),
             })
         }
@@ -162,9 +895,17 @@ fn convert_node_to_snode(node: &Rc<Node>) -> Option<SNode> {
             name: name.to_string(),
             kind: "doctype".to_string(),
         }),
+        markup5ever_rcdom::NodeData::Text { ref contents } if is_lossless => Some(SNode::Text {
+            kind: TextKind::Text,
+            text: contents.borrow().to_string(),
+        }),
         markup5ever_rcdom::NodeData::Comment { ref contents } => Some(SNode::Comment {
             kind: "comment".to_string(),
-            text: Some(contents.trim().to_string()),
+            text: Some(if is_lossless {
+                contents.to_string()
+            } else {
+                contents.trim().to_string()
+            }),
         }),
         markup5ever_rcdom::NodeData::ProcessingInstruction {
             ref target,
@@ -179,196 +920,983 @@ fn convert_node_to_snode(node: &Rc<Node>) -> Option<SNode> {
     }
 }
 
-fn serialize_nodes_to_html(
-    settings: SerializeSettings,
-    nodes: &Vec<Rc<Node>>,
+// Yaml and toml render the same tree as JSON, or the texts and attributes extracted from every node
+fn serialize_nodes_to_data_format(
+    settings: &SerializeSettings,
+    nodes: &[Rc<Node>],
 ) -> io::Result<String> {
-    nodes.iter().fold(Ok(String::new()), |acc, node| {
-        let mut buffer = String::new();
-        let serializer = SerializableHandle::from(node.to_owned());
-
-        let mut ser: HtmlSerializer = HtmlSerializer::new(settings.to_owned(), &mut buffer);
-        serializer.serialize(&mut ser, TraversalScope::IncludeNode)?;
-
-        if !buffer.is_empty() {
-            match acc {
-                // Every extra whitespaces is removed and a new line is added to separate every node
-                // to be processed easily in a pipe
-                Ok(s) => Ok((if s.is_empty() { s } else { s + "\n" })
-                    + RegexBuilder::new(r">\s+<")
-                        .build()
-                        .unwrap()
-                        .replace_all(buffer.as_str().trim(), "><")
-                        .replace("\n", "")
-                        .as_ref()),
-                e => e,
-            }
+    if settings.should_render_text_only || settings.should_render_attributes {
+        let settings = SerializeSettings {
+            is_color_enabled: false,
+            ..settings.to_owned()
+        };
+        let values = nodes
+            .iter()
+            .map(|node| serialize_node_to_html(&settings, node))
+            .collect::<io::Result<Vec<Option<String>>>>()?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<String>>();
+        let key = if settings.should_render_text_only {
+            "texts"
         } else {
-            acc
-        }
-    })
+            "attributes"
+        };
+        serialize_to_data_format(&settings, key, &values)
+    } else if settings.json_format == JsonFormat::Pup {
+        let values = nodes
+            .iter()
+            .flat_map(|node| convert_node_to_pup_value(settings, node))
+            .collect::<Vec<serde_json::Value>>();
+        serialize_to_data_format(settings, "nodes", &values)
+    } else {
+        let snodes = nodes
+            .iter()
+            .flat_map(|node| convert_node_to_snode(settings, node))
+            .collect::<Vec<SNode>>();
+        serialize_to_data_format(settings, "nodes", &snodes)
+    }
 }
 
-#[derive(Default)]
-struct ElemInfo {
-    html_name: Option<LocalName>,
-    ignore_children: bool,
+// Toml has no null value, missing data is left out
+fn serialize_to_data_format<T: serde::Serialize>(
+    settings: &SerializeSettings,
+    key: &str,
+    value: &T,
+) -> io::Result<String> {
+    let result = if settings.is_toml_enabled {
+        let mut value = serde_json::to_value(value).map_err(io::Error::other)?;
+        fill_attribute_values(&mut value);
+        toml::to_string(&HashMap::from([(key, value)])).map_err(io::Error::other)
+    } else {
+        serde_yaml::to_string(value).map_err(io::Error::other)
+    };
+    result.map(|v| v.trim_end().to_string())
 }
 
-// This serializer is cloned from https://github.com/servo/html5ever/blob/57eb334c0ffccc6f88d563419f0fbeef6ff5741c/html5ever/src/serialize/mod.rs#L77
-pub struct HtmlSerializer<'a> {
-    colorizer: Colorizer,
-    settings: SerializeSettings,
-    stack: Vec<ElemInfo>,
-    buffer: &'a mut String,
+// Toml has no null value, an attribute without value (e.g. disabled) would be left out so it gets an empty value.
+// The other null values are left out
+fn fill_attribute_values(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Array(values) => values.iter_mut().for_each(fill_attribute_values),
+        serde_json::Value::Object(map) => {
+            *map = std::mem::take(map)
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .collect();
+            for (key, value) in map.iter_mut() {
+                match (key.as_str(), value) {
+                    ("attributes", serde_json::Value::Object(attributes)) => attributes
+                        .values_mut()
+                        .filter(|value| value.is_null())
+                        .for_each(|value| *value = serde_json::Value::String(String::new())),
+                    (_, value) => fill_attribute_values(value),
+                }
+            }
+        }
+        _ => (),
+    }
 }
 
-impl<'a> HtmlSerializer<'a> {
-    pub fn new(settings: SerializeSettings, buffer: &'a mut String) -> Self {
-        HtmlSerializer {
-            colorizer: Colorizer::new(settings.is_color_enabled),
-            settings,
-            stack: vec![ElemInfo {
-                html_name: None,
-                ignore_children: false,
-            }],
-            buffer,
+// Every node is rendered as a record, the columns are the requested attributes in order,
+// a pattern holding a wildcard stands for the matching attributes found on the nodes
+fn write_attribute_records(
+    settings: &SerializeSettings,
+    attributes_format: AttributesFormat,
+    colorizer: Colorizer,
+    nodes: &[Rc<Node>],
+    output: &mut dyn Write,
+) -> io::Result<bool> {
+    let attributes = nodes
+        .iter()
+        .map(|node| match &node.data {
+            NodeData::Element { attrs, .. } => attrs
+                .borrow()
+                .iter()
+                .map(|attr| {
+                    let value = if settings.is_raw_enabled {
+                        attr.value.to_string()
+                    } else {
+                        resolve_url(&settings.base_url, &attr.name.local, attr.value.trim())
+                    };
+                    (attr.name.local.to_string(), value)
+                })
+                .collect::<Vec<(String, String)>>(),
+            _ => vec![],
+        })
+        .collect::<Vec<Vec<(String, String)>>>();
+
+    let mut columns: Vec<String> = vec![];
+    for pattern in settings.attributes.iter() {
+        if !pattern.contains('*') {
+            if !columns.contains(pattern) {
+                columns.push(pattern.to_owned());
+            }
+            continue;
+        }
+        for (name, _) in attributes.iter().flatten() {
+            if is_matching_attribute(pattern, name) && !columns.contains(name) {
+                columns.push(name.to_owned());
+            }
         }
     }
 
-    fn parent(&mut self) -> &mut ElemInfo {
-        if self.stack.len() == 0 {
-            self.stack.push(Default::default());
+    let records = attributes.iter().map(|attributes| {
+        columns
+            .iter()
+            .map(|column| {
+                attributes
+                    .iter()
+                    .find(|(name, _)| name == column)
+                    .map(|(_, value)| value.to_owned())
+            })
+            .collect::<Vec<Option<String>>>()
+    });
+
+    match attributes_format {
+        AttributesFormat::Json => write_records(
+            output,
+            "\n",
+            records.map(|values| {
+                serde_json::to_string(&AttributeRecord(&columns, values))
+                    .map(|json| Some(colorizer.colorize_json(&json)))
+                    .map_err(io::Error::other)
+            }),
+        ),
+        // Tsv has no quoting, tabs and new lines are replaced with a space
+        AttributesFormat::Tsv => {
+            let escape = |value: &str| value.replace(['\t', '\n', '\r'], " ");
+            write_records(
+                output,
+                "\n",
+                std::iter::once(
+                    columns
+                        .iter()
+                        .map(|column| escape(column))
+                        .collect::<Vec<String>>(),
+                )
+                .chain(records.map(|values| {
+                    values
+                        .iter()
+                        .map(|value| escape(value.as_deref().unwrap_or_default()))
+                        .collect()
+                }))
+                .map(|row| Ok(Some(row.join("\t")))),
+            )
         }
-        self.stack.last_mut().unwrap()
     }
+}
 
-    fn write_escaped(&mut self, text: &str, attr_mode: bool) -> String {
-        text.chars().fold(String::new(), |acc, c| {
-            acc + match c {
-                '&' => String::from("&amp;"),
-                '\u{00A0}' => String::from("&nbsp;"),
-                '"' if attr_mode => String::from("&quot;"),
-                '<' if !attr_mode => String::from("&lt;"),
-                '>' if !attr_mode => String::from("&gt;"),
-                c => c.to_string(),
-            }
-            .as_str()
-        })
+// The attributes of a node rendered as a JSON object, the properties keep the order of the columns
+struct AttributeRecord<'a>(&'a [String], Vec<Option<String>>);
+
+impl serde::Serialize for AttributeRecord<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in self.0.iter().zip(self.1.iter()) {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
     }
 }
 
-impl<'b> Serializer for HtmlSerializer<'b> {
-    fn start_elem<'a, AttrIter>(&mut self, name: QualName, attrs: AttrIter) -> io::Result<()>
-    where
-        AttrIter: Iterator<Item = AttrRef<'a>>,
-    {
-        let html_name = match name.ns {
-            ns!(html) => Some(name.local.clone()),
-            _ => None,
-        };
+// An attribute pattern is either a name or a glob where * matches any sequence of characters (e.g. data-*)
+pub fn is_matching_attribute(pattern: &str, name: &str) -> bool {
+    let parts = pattern.split('*').collect::<Vec<&str>>();
+    if parts.len() == 1 {
+        return pattern == name;
+    }
 
-        if self.parent().ignore_children {
-            self.stack.push(ElemInfo {
-                html_name,
-                ignore_children: true,
-            });
-            return Ok(());
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if !name.starts_with(first) || name.len() < first.len() + last.len() || !name.ends_with(last) {
+        return false;
+    }
+    let mut rest = &name[first.len()..name.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
         }
+    }
+    true
+}
 
-        if !self.settings.should_render_text_only && !self.settings.should_render_attributes {
-            self.buffer.push_str(
-                self.colorizer
-                    .colorize(format!("<{}", tagname(&name).trim()), Color::Magenta)
-                    .as_str(),
-            );
-        }
-        for (name, value) in attrs {
-            if !self.settings.should_render_text_only && !self.settings.should_render_attributes {
-                self.buffer.push_str(" ");
-                match name.ns {
-                    ns!() => (),
-                    ns!(xml) => self
-                        .buffer
-                        .push_str(self.colorizer.colorize("xml:", Color::Magenta).as_ref()),
-                    ns!(xmlns) => {
-                        if name.local != local_name!("xmlns") {
-                            self.buffer.push_str(
-                                self.colorizer.colorize("xmlns:", Color::Magenta).as_ref(),
-                            );
+// Every node is printed on its own line, colors are never used since the output is meant to be processed
+fn serialize_node_to_format_string(
+    settings: &SerializeSettings,
+    format_string: &FormatString,
+    index: usize,
+    node: &Rc<Node>,
+) -> io::Result<String> {
+    let html_settings = SerializeSettings {
+        is_color_enabled: false,
+        should_render_text_only: false,
+        should_render_attributes: false,
+        format_string: None,
+        ..settings.to_owned()
+    };
+    let text_settings = SerializeSettings {
+        should_render_text_only: true,
+        ..html_settings.to_owned()
+    };
+
+    let mut line = String::new();
+    for segment in format_string.segments.iter() {
+        match segment {
+            FormatSegment::Literal(literal) => line.push_str(literal),
+            FormatSegment::Name => {
+                if let NodeData::Element { name, .. } = &node.data {
+                    match &name.prefix {
+                        Some(prefix) if settings.is_xml_enabled => {
+                            line.push_str(&format!("{}:{}", prefix, name.local))
                         }
-                    }
-                    ns!(xlink) => self
-                        .buffer
-                        .push_str(self.colorizer.colorize("xlink:", Color::Magenta).as_ref()),
-                    _ => {
-                        self.buffer.push_str(
-                            self.colorizer
-                                .colorize("unknown_namespace:", Color::Magenta)
-                                .as_ref(),
-                        );
+                        _ => line.push_str(name.local.as_ref()),
                     }
                 }
-
-                self.buffer.push_str(
+            }
+            FormatSegment::Attribute(name) => {
+                if let NodeData::Element { attrs, .. } = &node.data {
+                    if let Some(attr) = attrs
+                        .borrow()
+                        .iter()
+                        .find(|attr| attr.name.local.as_ref() == name)
+                    {
+                        let value = match settings.is_raw_enabled {
+                            true => attr.value.to_string(),
+                            false => resolve_url(&settings.base_url, name, attr.value.trim()),
+                        };
+                        line.push_str(&value);
+                    }
+                }
+            }
+            FormatSegment::Text => {
+                line.push_str(&serialize_node_to_html(&text_settings, node)?.unwrap_or_default())
+            }
+            FormatSegment::InnerHtml => line.push_str(
+                &serialize_markup(&html_settings, node, TraversalScope::ChildrenOnly(None))?
+                    .unwrap_or_default(),
+            ),
+            FormatSegment::OuterHtml => {
+                line.push_str(&serialize_node_to_html(&html_settings, node)?.unwrap_or_default())
+            }
+            FormatSegment::Index => line.push_str(&(settings.first_index + index + 1).to_string()),
+            FormatSegment::Source => line.push_str(settings.source.as_deref().unwrap_or_default()),
+            FormatSegment::Selector => {
+                line.push_str(settings.selector.as_deref().unwrap_or_default())
+            }
+        }
+    }
+
+    Ok(line)
+}
+
+enum TraversalStep {
+    Open(Handle),
+    Close(QualName),
+}
+
+// Same traversal as SerializableHandle::serialize, which inserts every step at the front of a vector and
+// becomes quadratic for nodes with many children. The steps are pushed in reverse order on a stack instead
+fn traverse<S: Serializer>(
+    serializer: &mut S,
+    node: &Handle,
+    scope: TraversalScope,
+) -> io::Result<()> {
+    let push_children = |steps: &mut Vec<TraversalStep>, node: &Handle| {
+        steps.extend(
+            node.children
+                .borrow()
+                .iter()
+                .rev()
+                .map(|child| TraversalStep::Open(child.to_owned())),
+        )
+    };
+
+    let mut steps = vec![];
+    match scope {
+        TraversalScope::IncludeNode => steps.push(TraversalStep::Open(node.to_owned())),
+        TraversalScope::ChildrenOnly(_) => push_children(&mut steps, node),
+    }
+
+    while let Some(step) = steps.pop() {
+        match step {
+            TraversalStep::Open(node) => match &node.data {
+                NodeData::Element { name, attrs, .. } => {
+                    serializer.start_elem(
+                        name.to_owned(),
+                        attrs
+                            .borrow()
+                            .iter()
+                            .map(|attr| (&attr.name, attr.value.as_ref())),
+                    )?;
+                    steps.push(TraversalStep::Close(name.to_owned()));
+                    push_children(&mut steps, &node);
+                }
+                NodeData::Doctype { name, .. } => serializer.write_doctype(name)?,
+                NodeData::Text { contents } => serializer.write_text(&contents.borrow())?,
+                NodeData::Comment { contents } => serializer.write_comment(contents)?,
+                NodeData::ProcessingInstruction { target, contents } => {
+                    serializer.write_processing_instruction(target, contents)?
+                }
+                // Like SerializableHandle, a document can't be serialized itself
+                NodeData::Document => push_children(&mut steps, &node),
+            },
+            TraversalStep::Close(name) => serializer.end_elem(name)?,
+        }
+    }
+
+    Ok(())
+}
+
+// Renders a single node with settings built once by a caller rendering many nodes, e.g. the cells of a table
+pub fn serialize_node(settings: &SerializeSettings, node: &Rc<Node>) -> io::Result<Option<String>> {
+    serialize_node_to_html(settings, node)
+}
+
+fn serialize_node_to_html(
+    settings: &SerializeSettings,
+    node: &Rc<Node>,
+) -> io::Result<Option<String>> {
+    serialize_markup(settings, node, TraversalScope::IncludeNode)
+}
+
+fn serialize_markup(
+    settings: &SerializeSettings,
+    node: &Rc<Node>,
+    scope: TraversalScope,
+) -> io::Result<Option<String>> {
+    let mut buffer = vec![];
+    if !write_markup(settings, node, scope, "", &mut buffer)? {
+        return Ok(None);
+    }
+    String::from_utf8(buffer)
+        .map(Some)
+        .map_err(io::Error::other)
+}
+
+// Writes the markup of the node straight to the output, preceded by the separator unless it renders nothing.
+// Returns whether something was written
+fn write_markup(
+    settings: &SerializeSettings,
+    node: &Rc<Node>,
+    scope: TraversalScope,
+    separator: &str,
+    output: &mut dyn Write,
+) -> io::Result<bool> {
+    let whitespaces = if settings.should_render_text_only
+        && (settings.is_text_node_per_line || settings.text_mode == TextMode::Inner)
+    {
+        // Texts are written on their own line, every line is trimmed
+        Whitespaces::Lines
+    } else if settings.is_raw_enabled || settings.is_minify_enabled {
+        // The serializer already laid out the markup
+        Whitespaces::Kept
+    } else if settings.indent.is_some() {
+        Whitespaces::Trimmed
+    } else {
+        // Every extra whitespaces is removed
+        Whitespaces::Squeezed
+    };
+    let mut writer = MarkupWriter {
+        output,
+        separator,
+        whitespaces,
+        pending: String::new(),
+        last: None,
+        is_written: false,
+    };
+
+    let mut ser = HtmlSerializer::new(settings.to_owned(), &mut writer);
+    traverse(&mut ser, node, scope)?;
+    ser.finish()?;
+
+    Ok(writer.is_written)
+}
+
+// How the whitespaces written by the serializer are laid out
+#[derive(Clone, Copy, PartialEq)]
+enum Whitespaces {
+    Kept,
+    // Removed at the beginning and at the end
+    Trimmed,
+    // Trimmed, removed between tags and new lines are dropped
+    Squeezed,
+    // Every sequence of whitespaces becomes a new line when it holds one, a space otherwise
+    Lines,
+}
+
+// Whitespaces are held until something else is written, so they can be dropped once we know what surrounds them
+struct MarkupWriter<'a> {
+    output: &'a mut dyn Write,
+    separator: &'a str,
+    whitespaces: Whitespaces,
+    pending: String,
+    // The last character written, apart from whitespaces
+    last: Option<char>,
+    is_written: bool,
+}
+
+impl MarkupWriter<'_> {
+    fn write_str(&mut self, text: &str) -> io::Result<()> {
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            let is_whitespace = c.is_whitespace() && self.whitespaces != Whitespaces::Kept;
+            let end = rest
+                .find(|c: char| c.is_whitespace() != is_whitespace)
+                .filter(|_| self.whitespaces != Whitespaces::Kept)
+                .unwrap_or(rest.len());
+            let (segment, remaining) = rest.split_at(end);
+            rest = remaining;
+
+            if is_whitespace {
+                self.pending.push_str(segment);
+                continue;
+            }
+            self.write_pending(c)?;
+            self.output.write_all(segment.as_bytes())?;
+            self.last = segment.chars().last();
+        }
+        Ok(())
+    }
+
+    fn write_pending(&mut self, next: char) -> io::Result<()> {
+        if !self.is_written {
+            self.is_written = true;
+            self.pending.clear();
+            return self.output.write_all(self.separator.as_bytes());
+        }
+
+        match self.whitespaces {
+            Whitespaces::Lines if self.pending.contains('\n') => self.output.write_all(b"\n")?,
+            Whitespaces::Lines if !self.pending.is_empty() => self.output.write_all(b" ")?,
+            Whitespaces::Squeezed if self.last == Some('>') && next == '<' => (),
+            Whitespaces::Squeezed => {
+                for line in self.pending.split('\n') {
+                    self.output.write_all(line.as_bytes())?;
+                }
+            }
+            _ => self.output.write_all(self.pending.as_bytes())?,
+        }
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+// The serializer only writes whole strings
+impl Write for MarkupWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text =
+            std::str::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.write_str(text)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+#[derive(Default)]
+struct ElemInfo {
+    html_name: Option<LocalName>,
+    ignore_children: bool,
+    // Namespaces declared by the element in xml mode
+    namespaces: Vec<(Option<Prefix>, Namespace)>,
+    // Used in pretty mode, a block element starts on a new line
+    is_block: bool,
+    // Used in pretty mode, the end tag of an element having block children is written on its own line
+    has_block_children: bool,
+    // Used in pretty mode, the content of elements like pre is written as is
+    is_preformatted: bool,
+    // Used when extracting the inner text, the element or one of its ancestors is not displayed
+    is_hidden: bool,
+    // The parser drops the new line starting the content of pre, textarea and listing elements,
+    // it is written again until the first child is written
+    is_leading_new_line_dropped: bool,
+}
+
+// This serializer is cloned from https://github.com/servo/html5ever/blob/57eb334c0ffccc6f88d563419f0fbeef6ff5741c/html5ever/src/serialize/mod.rs#L77
+pub struct HtmlSerializer<W: Write> {
+    colorizer: Colorizer,
+    settings: SerializeSettings,
+    stack: Vec<ElemInfo>,
+    output: W,
+    // Nothing but spaces removed at the end of a line was written yet
+    is_empty: bool,
+    // In pretty mode the spaces ending a line are only written once something follows them on the line
+    trailing_spaces: usize,
+    // In xml mode the ">" ending a start tag is only written once we know the element is not empty
+    is_start_tag_open: bool,
+    // In pretty mode the content following a block element starts on a new line
+    is_new_line_needed: bool,
+    // In pretty mode whitespaces are dropped at the beginning of a line
+    is_line_start: bool,
+    // In minify mode collapsed whitespaces are only written once followed by inline content
+    is_whitespace_pending: bool,
+    // In minify mode whitespaces are dropped at the beginning and at the end of a block
+    is_after_boundary: bool,
+    // In minify mode an end tag which may be omitted, depending on what follows it
+    pending_end_tag: Option<QualName>,
+}
+
+impl<W: Write> HtmlSerializer<W> {
+    pub fn new(settings: SerializeSettings, output: W) -> Self {
+        HtmlSerializer {
+            colorizer: Colorizer::new(settings.is_color_enabled, settings.theme),
+            settings,
+            stack: vec![ElemInfo::default()],
+            output,
+            is_empty: true,
+            trailing_spaces: 0,
+            is_start_tag_open: false,
+            is_new_line_needed: false,
+            is_line_start: true,
+            is_whitespace_pending: false,
+            is_after_boundary: true,
+            pending_end_tag: None,
+        }
+    }
+
+    // Writes what is still pending once every node is serialized
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.resolve_pending_end_tag(Following::Content)?;
+        self.output
+            .write_all(" ".repeat(self.trailing_spaces).as_bytes())
+    }
+
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        if !self.is_pretty() {
+            self.is_empty &= text.is_empty();
+            return self.output.write_all(text.as_bytes());
+        }
+
+        let content = text.trim_end_matches(' ');
+        if !content.is_empty() {
+            self.is_empty = false;
+            self.output
+                .write_all(" ".repeat(self.trailing_spaces).as_bytes())?;
+            self.output.write_all(content.as_bytes())?;
+            self.trailing_spaces = 0;
+        }
+        self.trailing_spaces += text.len() - content.len();
+        Ok(())
+    }
+
+    fn parent(&mut self) -> &mut ElemInfo {
+        if self.stack.is_empty() {
+            self.stack.push(Default::default());
+        }
+        self.stack.last_mut().unwrap()
+    }
+
+    fn write_escaped(&mut self, text: &str, attr_mode: bool) -> String {
+        let is_xml_enabled = self.settings.is_xml_enabled;
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                // &nbsp; is not a predefined xml entity
+                '\u{00A0}' if !is_xml_enabled => escaped.push_str("&nbsp;"),
+                '"' if attr_mode => escaped.push_str("&quot;"),
+                '<' if !attr_mode || is_xml_enabled => escaped.push_str("&lt;"),
+                '>' if !attr_mode => escaped.push_str("&gt;"),
+                c => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    fn is_rendering_markup(&self) -> bool {
+        !self.settings.should_render_text_only && !self.settings.should_render_attributes
+    }
+
+    fn is_namespace_in_scope(&self, namespace: &(Option<Prefix>, Namespace)) -> bool {
+        self.stack
+            .iter()
+            .rev()
+            .flat_map(|info| info.namespaces.iter())
+            .find(|(prefix, _)| prefix == &namespace.0)
+            .map_or_else(
+                || namespace.0.is_none() && namespace.1 == ns!(),
+                |(_, ns)| ns == &namespace.1,
+            )
+    }
+
+    // Whitespaces surrounding texts and values are kept in raw mode
+    fn trim<'s>(&self, text: &'s str) -> &'s str {
+        if self.settings.is_raw_enabled {
+            text
+        } else {
+            text.trim()
+        }
+    }
+
+    fn is_extracting_inner_text(&self) -> bool {
+        self.settings.should_render_text_only && self.settings.text_mode == TextMode::Inner
+    }
+
+    fn is_pretty(&self) -> bool {
+        self.settings.indent.is_some() && self.is_rendering_markup()
+    }
+
+    // Starts a new line indented according to the number of block elements opened
+    fn write_new_line(&mut self) -> io::Result<()> {
+        self.is_new_line_needed = false;
+        if !self.is_pretty() || self.stack.last().is_some_and(|info| info.is_preformatted) {
+            return Ok(());
+        }
+        let depth = self.stack.iter().filter(|info| info.is_block).count();
+        self.trim_line_end();
+        if !self.is_empty {
+            self.write("\n")?;
+        }
+        self.write(
+            " ".repeat(depth * self.settings.indent.unwrap_or(0))
+                .as_str(),
+        )?;
+        self.is_line_start = true;
+        Ok(())
+    }
+
+    fn trim_line_end(&mut self) {
+        self.trailing_spaces = 0;
+    }
+
+    // Inline content following a block element starts on a new line
+    fn write_pending_new_line(&mut self) -> io::Result<()> {
+        if self.is_new_line_needed {
+            self.write_new_line()?;
+        }
+        Ok(())
+    }
+
+    fn is_minifying(&self) -> bool {
+        self.settings.is_minify_enabled && self.is_rendering_markup()
+    }
+
+    // The pending end tag is written unless what follows it implies it
+    fn resolve_pending_end_tag(&mut self, following: Following) -> io::Result<()> {
+        if let Some(name) = self.pending_end_tag.take() {
+            if !can_omit_end_tag(&name.local, following) {
+                self.write_end_tag(&name)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_pending_whitespace(&mut self) -> io::Result<()> {
+        if self.is_whitespace_pending {
+            self.is_whitespace_pending = false;
+            self.write(" ")?;
+        }
+        Ok(())
+    }
+
+    fn write_end_tag(&mut self, name: &QualName) -> io::Result<()> {
+        self.write(
+            self.colorizer
+                .colorize(
+                    format!("</{}>", qualified_name(&self.settings, name).trim()),
+                    Style::Tag,
+                )
+                .as_ref(),
+        )
+    }
+
+    // Whitespaces are collapsed to a single space, written once something visible follows it on the line
+    fn write_minified_text(&mut self, text: &str, escape: bool) -> io::Result<()> {
+        let parent = self.parent();
+        if parent.is_preformatted || !escape {
+            self.resolve_pending_end_tag(Following::Content)?;
+            self.write_pending_whitespace()?;
+            let v = if escape {
+                self.write_escaped(text, false)
+            } else {
+                text.to_string()
+            };
+            self.write(v.as_str())?;
+            self.is_after_boundary = false;
+            return Ok(());
+        }
+        // Only elements can be laid out in these elements
+        if text.chars().all(|c| c.is_ascii_whitespace())
+            && parent.html_name.as_ref().is_some_and(is_text_free_element)
+        {
+            return Ok(());
+        }
+
+        let text = HTML_WHITESPACES.replace_all(text, " ");
+        if text.starts_with(' ') && !self.is_after_boundary {
+            self.is_whitespace_pending = true;
+        }
+        let trimmed = text.trim_matches(' ');
+        if trimmed.is_empty() {
+            return Ok(());
+        }
+
+        self.resolve_pending_end_tag(Following::Content)?;
+        self.write_pending_whitespace()?;
+        let v = self.write_escaped(trimmed, false);
+        self.write(v.as_str())?;
+        self.is_after_boundary = false;
+        self.is_whitespace_pending = text.ends_with(' ');
+        Ok(())
+    }
+
+    fn close_start_tag(&mut self) -> io::Result<()> {
+        if self.is_start_tag_open {
+            self.is_start_tag_open = false;
+            self.write(self.colorizer.colorize(">", Style::Tag).as_ref())?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Serializer for HtmlSerializer<W> {
+    fn start_elem<'a, AttrIter>(&mut self, name: QualName, attrs: AttrIter) -> io::Result<()>
+    where
+        AttrIter: Iterator<Item = AttrRef<'a>>,
+    {
+        let html_name = match name.ns {
+            ns!(html) => Some(name.local.clone()),
+            _ => None,
+        };
+
+        if self.parent().ignore_children {
+            self.stack.push(ElemInfo {
+                html_name,
+                ignore_children: true,
+                ..Default::default()
+            });
+            return Ok(());
+        }
+        let is_inner_text = self.is_extracting_inner_text();
+
+        self.parent().is_leading_new_line_dropped = false;
+        self.close_start_tag()?;
+
+        // Every xml element is laid out as a block
+        let is_preformatted = self.parent().is_preformatted;
+        let is_block = self.is_pretty()
+            && !is_preformatted
+            && (self.settings.is_xml_enabled || is_block_element(&name));
+        if is_block {
+            self.parent().has_block_children = true;
+            self.write_new_line()?;
+        } else {
+            self.write_pending_new_line()?;
+        }
+
+        let is_whitespace_boundary = is_whitespace_boundary(&name);
+        if self.is_minifying() {
+            self.resolve_pending_end_tag(Following::Element(&name.local))?;
+            if is_whitespace_boundary {
+                self.is_whitespace_pending = false;
+            } else {
+                self.write_pending_whitespace()?;
+            }
+            self.is_after_boundary = is_whitespace_boundary;
+        }
+
+        if !self.settings.should_render_text_only && !self.settings.should_render_attributes {
+            self.write(
+                self.colorizer
+                    .colorize(
+                        format!("<{}", qualified_name(&self.settings, &name).trim()),
+                        Style::Tag,
+                    )
+                    .as_str(),
+            )?;
+        }
+        // The xml parser drops namespace declarations, they are declared again
+        // on the first element of the output using them
+        let attrs = attrs.collect::<Vec<AttrRef>>();
+        let is_hidden =
+            self.parent().is_hidden || is_inner_text && is_hidden_element(&name, &attrs);
+        // Like browsers do, the inner text of a block element or of a line break starts on a new line
+        if is_inner_text
+            && !is_hidden
+            && (is_block_element(&name) || name.local == local_name!("br"))
+        {
+            self.write("\n")?;
+        }
+        let mut namespaces: Vec<(Option<Prefix>, Namespace)> = vec![];
+        if self.settings.is_xml_enabled {
+            // Unprefixed attributes don't belong to the default namespace
+            let attribute_names = attrs
+                .iter()
+                .map(|(name, _)| *name)
+                .filter(|name| name.prefix.is_some());
+            for name in std::iter::once(&name).chain(attribute_names) {
+                let namespace = (name.prefix.to_owned(), name.ns.to_owned());
+                if namespace.0 == Some(namespace_prefix!("xml"))
+                    || namespaces.contains(&namespace)
+                    || self.is_namespace_in_scope(&namespace)
+                {
+                    continue;
+                }
+                namespaces.push(namespace);
+            }
+        }
+
+        if self.is_rendering_markup() {
+            for (prefix, ns) in namespaces.iter() {
+                self.write(" ")?;
+                self.write(
+                    self.colorizer
+                        .colorize(
+                            match prefix {
+                                Some(prefix) => format!("xmlns:{}", prefix),
+                                None => "xmlns".to_string(),
+                            },
+                            Style::AttributeName,
+                        )
+                        .as_ref(),
+                )?;
+                self.write(self.colorizer.colorize("=\"", Style::Tag).as_ref())?;
+                let v = self.write_escaped(ns.as_ref(), true);
+                self.write(self.colorizer.colorize(v, Style::AttributeValue).as_ref())?;
+                self.write(self.colorizer.colorize("\"", Style::Tag).as_ref())?;
+            }
+        }
+
+        for (name, value) in attrs {
+            let is_resolved_url = self.settings.base_url.is_some()
+                && (name.local.as_ref() == "srcset"
+                    || URL_ATTRIBUTES.contains(&name.local.as_ref()));
+            // Browsers read minified values byte for byte, only the urls being resolved are trimmed
+            let value = if self.is_minifying() && !is_resolved_url {
+                value.to_string()
+            } else {
+                resolve_url(
+                    &self.settings.base_url,
+                    name.local.as_ref(),
+                    self.trim(value.as_ref()),
+                )
+            };
+
+            if !self.settings.should_render_text_only && !self.settings.should_render_attributes {
+                self.write(" ")?;
+                match name.ns {
+                    // In xml mode the prefix is part of the qualified name
+                    _ if self.settings.is_xml_enabled => (),
+                    ns!() => (),
+                    ns!(xml) => self.write(self.colorizer.colorize("xml:", Style::Tag).as_ref())?,
+                    ns!(xmlns) => {
+                        if name.local != local_name!("xmlns") {
+                            self.write(self.colorizer.colorize("xmlns:", Style::Tag).as_ref())?;
+                        }
+                    }
+                    ns!(xlink) => {
+                        self.write(self.colorizer.colorize("xlink:", Style::Tag).as_ref())?
+                    }
+                    _ => {
+                        self.write(
+                            self.colorizer
+                                .colorize("unknown_namespace:", Style::Tag)
+                                .as_ref(),
+                        )?;
+                    }
+                }
+
+                self.write(
                     self.colorizer
-                        .colorize(name.local.trim(), Color::Yellow)
+                        .colorize(
+                            qualified_name(&self.settings, name).trim(),
+                            Style::AttributeName,
+                        )
                         .trim(),
+                )?;
+                let v = self.colorizer.colorize(
+                    self.write_escaped(value.as_str(), true),
+                    Style::AttributeValue,
                 );
-                self.buffer
-                    .push_str(self.colorizer.colorize("=\"", Color::Magenta).as_ref());
-                let v = self
-                    .colorizer
-                    .colorize(self.write_escaped(value.trim(), true), Color::Green);
-                self.buffer.push_str(v.to_string().as_str());
-                self.buffer
-                    .push_str(self.colorizer.colorize("\"", Color::Magenta).as_ref());
-            }
-
-            if self.settings.should_render_attributes {
-                if self
-                    .settings
-                    .attributes
-                    .contains(&name.local.trim().to_string())
+                if self.is_minifying()
+                    && (value.is_empty() || is_boolean_attribute(name, value.as_str()))
                 {
-                    self.buffer.push_str(value.trim());
-                    self.buffer.push(' ');
+                    // A boolean attribute is true as soon as it is present, whatever its value
+                } else if self.is_minifying() && is_unquoted_value_allowed(value.as_str()) {
+                    self.write(self.colorizer.colorize("=", Style::Tag).as_ref())?;
+                    self.write(v.to_string().as_str())?;
+                } else {
+                    self.write(self.colorizer.colorize("=\"", Style::Tag).as_ref())?;
+                    self.write(v.to_string().as_str())?;
+                    self.write(self.colorizer.colorize("\"", Style::Tag).as_ref())?;
                 }
             }
+
+            if self.settings.should_render_attributes
+                && self
+                    .settings
+                    .attributes
+                    .iter()
+                    .any(|pattern| is_matching_attribute(pattern, name.local.trim()))
+            {
+                self.write(value.as_str())?;
+                self.write(" ")?;
+            }
         }
 
-        if !self.settings.should_render_text_only && !self.settings.should_render_attributes {
-            self.buffer
-                .push_str(self.colorizer.colorize(">", Color::Magenta).as_ref());
+        if self.settings.is_xml_enabled && self.is_rendering_markup() {
+            self.is_start_tag_open = true;
+        } else if self.is_rendering_markup() {
+            self.write(self.colorizer.colorize(">", Style::Tag).as_ref())?;
         }
 
-        let ignore_children = name.ns == ns!(html)
-            && match name.local {
+        // Xml has no void elements, an empty element is self-closed instead
+        let ignore_children = !self.settings.is_xml_enabled
+            && name.ns == ns!(html)
+            && matches!(
+                name.local,
                 local_name!("area")
-                | local_name!("base")
-                | local_name!("basefont")
-                | local_name!("bgsound")
-                | local_name!("br")
-                | local_name!("col")
-                | local_name!("embed")
-                | local_name!("frame")
-                | local_name!("hr")
-                | local_name!("img")
-                | local_name!("input")
-                | local_name!("keygen")
-                | local_name!("link")
-                | local_name!("meta")
-                | local_name!("param")
-                | local_name!("source")
-                | local_name!("track")
-                | local_name!("wbr") => true,
-                _ => false,
-            };
+                    | local_name!("base")
+                    | local_name!("basefont")
+                    | local_name!("bgsound")
+                    | local_name!("br")
+                    | local_name!("col")
+                    | local_name!("embed")
+                    | local_name!("frame")
+                    | local_name!("hr")
+                    | local_name!("img")
+                    | local_name!("input")
+                    | local_name!("keygen")
+                    | local_name!("link")
+                    | local_name!("meta")
+                    | local_name!("param")
+                    | local_name!("source")
+                    | local_name!("track")
+                    | local_name!("wbr")
+            );
 
         self.stack.push(ElemInfo {
+            is_preformatted: is_preformatted
+                || (!self.settings.is_xml_enabled
+                    && matches!(
+                        name.local,
+                        local_name!("pre")
+                            | local_name!("textarea")
+                            | local_name!("listing")
+                            | local_name!("script")
+                            | local_name!("style")
+                    )),
+            is_leading_new_line_dropped: self.is_rendering_markup()
+                && !self.settings.is_xml_enabled
+                && matches!(
+                    html_name,
+                    Some(local_name!("pre"))
+                        | Some(local_name!("textarea"))
+                        | Some(local_name!("listing"))
+                ),
             html_name,
             ignore_children,
+            namespaces,
+            is_block,
+            has_block_children: false,
+            is_hidden,
         });
+        self.is_line_start = is_block;
 
         Ok(())
     }
@@ -379,90 +1907,510 @@ impl<'b> Serializer for HtmlSerializer<'b> {
             _ => panic!("no ElemInfo"),
         };
         if info.ignore_children {
+            self.is_new_line_needed = info.is_block;
             return Ok(());
         }
 
-        if !self.settings.should_render_text_only && !self.settings.should_render_attributes {
-            self.buffer.push_str(
-                self.colorizer
-                    .colorize(format!("</{}>", tagname(&name).trim()), Color::Magenta)
-                    .as_ref(),
-            );
+        if self.is_extracting_inner_text() && !info.is_hidden && is_block_element(&name) {
+            self.write("\n")?;
+        }
+
+        if info.has_block_children {
+            self.write_new_line()?;
+        } else if info.is_block && !info.is_preformatted {
+            self.trim_line_end();
         }
 
+        if self.is_minifying() {
+            self.resolve_pending_end_tag(Following::ParentEnd(&name))?;
+            if is_whitespace_boundary(&name) {
+                self.is_whitespace_pending = false;
+                self.is_after_boundary = true;
+            }
+        }
+
+        if self.is_start_tag_open {
+            self.is_start_tag_open = false;
+            self.write(self.colorizer.colorize("/>", Style::Tag).as_ref())?;
+        } else if self.is_minifying() && name.ns == ns!(html) && is_end_tag_optional(&name.local) {
+            self.pending_end_tag = Some(name);
+        } else if self.is_rendering_markup() {
+            self.write_end_tag(&name)?;
+        }
+        self.is_new_line_needed = info.is_block;
+        self.is_line_start = false;
+
         Ok(())
     }
 
     fn write_text(&mut self, text: &str) -> io::Result<()> {
-        let escape = match self.parent().html_name {
-            Some(local_name!("style"))
-            | Some(local_name!("script"))
-            | Some(local_name!("xmp"))
-            | Some(local_name!("iframe"))
-            | Some(local_name!("noembed"))
-            | Some(local_name!("noframes"))
-            | Some(local_name!("plaintext"))
-            | Some(local_name!("noscript")) => false,
-            _ => true,
+        // A text starting with a new line would lose it when parsed again, unless a new line is written before it
+        let text = if std::mem::take(&mut self.parent().is_leading_new_line_dropped)
+            && text.starts_with('\n')
+        {
+            Cow::Owned(format!("\n{}", text))
+        } else {
+            Cow::Borrowed(text)
         };
+        let text = text.as_ref();
+        let escape = self.settings.is_xml_enabled
+            || !matches!(
+                self.parent().html_name,
+                Some(local_name!("style"))
+                    | Some(local_name!("script"))
+                    | Some(local_name!("xmp"))
+                    | Some(local_name!("iframe"))
+                    | Some(local_name!("noembed"))
+                    | Some(local_name!("noframes"))
+                    | Some(local_name!("plaintext"))
+                    | Some(local_name!("noscript"))
+            );
 
         if self.settings.should_render_attributes
             || (self.settings.should_render_text_only
                 && (text.trim().is_empty()
                     || Some(local_name!("style")) == self.parent().html_name
-                    || Some(local_name!("script")) == self.parent().html_name))
+                    || Some(local_name!("script")) == self.parent().html_name
+                    // The root element of the stack stands for the serialized node itself
+                    || (self.settings.text_mode == TextMode::Own && self.stack.len() > 2)
+                    || self.parent().is_hidden))
+        {
+            return Ok(());
+        }
+
+        if self.is_minifying() {
+            self.write_minified_text(text, escape)?;
+            return Ok(());
+        }
+
+        let is_preformatted = self.is_pretty() && self.parent().is_preformatted;
+
+        // Whitespaces are trimmed anyway, skipping them allows to self-close elements
+        if self.settings.is_xml_enabled
+            && !self.settings.is_raw_enabled
+            && !is_preformatted
+            && text.trim().is_empty()
         {
             return Ok(());
         }
 
-        if escape {
+        // In pretty mode whitespaces are collapsed instead of trimmed to keep the words separated
+        let text = if self.is_pretty() && !is_preformatted {
+            let text = WHITESPACES.replace_all(text, " ");
+            let text = if self.is_line_start || self.is_new_line_needed {
+                text.trim_start().to_string()
+            } else {
+                text.to_string()
+            };
+            if text.is_empty() {
+                return Ok(());
+            }
+            text
+        } else {
+            text.to_string()
+        };
+        let text = text.as_str();
+
+        self.close_start_tag()?;
+        self.write_pending_new_line()?;
+
+        // The content of preformatted elements is kept intact
+        if is_preformatted {
+            let v = if escape {
+                self.write_escaped(text, false)
+            } else {
+                text.to_string()
+            };
+            self.write(v.as_str())?;
+            self.is_line_start = false;
+            return Ok(());
+        }
+
+        if self.settings.is_xml_enabled
+            && self.is_rendering_markup()
+            && (text.contains('<') || text.contains('&'))
+        {
+            // A CDATA section can't contain its own end marker, so it is split in two sections
+            self.write(
+                format!(
+                    "<![CDATA[{}]]>",
+                    self.trim(text).replace("]]>", "]]]]><![CDATA[>")
+                )
+                .as_str(),
+            )?;
+        } else if self.is_pretty() {
+            let v = if escape {
+                self.write_escaped(text, false)
+            } else {
+                text.to_string()
+            };
+            self.write(v.as_str())?;
+        } else if escape {
             let v = self.write_escaped(text, false);
-            self.buffer.push_str(v.trim());
+            let v = self.trim(v.as_str()).to_string();
+            self.write(v.as_str())?;
         } else {
-            self.buffer.push_str(text.trim());
+            let v = self.trim(text).to_string();
+            self.write(v.as_str())?;
         }
+        self.is_line_start = false;
 
-        if self.settings.should_render_text_only {
-            self.buffer.push_str(" ");
+        if self.settings.should_render_text_only && self.settings.is_text_node_per_line {
+            self.write("\n")?;
+        } else if self.settings.should_render_text_only {
+            self.write(" ")?;
         }
 
         Ok(())
     }
 
     fn write_comment(&mut self, text: &str) -> io::Result<()> {
+        if self.is_minifying() {
+            if !self.settings.should_keep_comments {
+                return Ok(());
+            }
+            self.resolve_pending_end_tag(Following::Content)?;
+        }
+        self.parent().is_leading_new_line_dropped = false;
+        self.close_start_tag()?;
+        self.write_pending_new_line()?;
+        self.is_line_start = false;
         if !self.settings.should_render_text_only && !self.settings.should_render_attributes {
-            self.buffer.push_str(
+            self.write(
                 self.colorizer
-                    .colorize(format!("<!--{}-->", text.trim()), Color::Blue)
+                    .colorize(format!("<!--{}-->", self.trim(text)), Style::Comment)
                     .as_ref(),
-            );
+            )?;
         }
         Ok(())
     }
 
     fn write_doctype(&mut self, name: &str) -> io::Result<()> {
+        self.resolve_pending_end_tag(Following::Content)?;
+        self.close_start_tag()?;
+        self.write_new_line()?;
         if !self.settings.should_render_text_only && !self.settings.should_render_attributes {
-            self.buffer.push_str(
+            self.write(
                 self.colorizer
-                    .colorize(format!("<!DOCTYPE {}>", name.trim()), Color::Blue)
+                    .colorize(format!("<!DOCTYPE {}>", name.trim()), Style::Comment)
                     .as_ref(),
-            );
+            )?;
+            // The parser drops the whitespaces following the doctype, a document rendered as found starts on the next line
+            if self.settings.is_raw_enabled {
+                self.write("\n")?;
+            }
         }
         Ok(())
     }
 
     fn write_processing_instruction(&mut self, target: &str, data: &str) -> io::Result<()> {
+        self.resolve_pending_end_tag(Following::Content)?;
+        self.close_start_tag()?;
+        self.write_new_line()?;
         if !self.settings.should_render_text_only && !self.settings.should_render_attributes {
-            self.buffer.push_str(
+            let end = if self.settings.is_xml_enabled {
+                "?>"
+            } else {
+                ">"
+            };
+            self.write(
                 self.colorizer
-                    .colorize(format!("<?{} {}>", target.trim(), data.trim()), Color::Blue)
+                    .colorize(
+                        format!("<?{} {}{}", target.trim(), self.trim(data), end),
+                        Style::Comment,
+                    )
                     .as_ref(),
-            );
+            )?;
         }
         Ok(())
     }
 }
 
+// Elements which are not displayed by browsers, their content is not part of the inner text
+fn is_hidden_element(name: &QualName, attrs: &[AttrRef]) -> bool {
+    let is_hidden_by_default = name.ns == ns!(html)
+        && matches!(
+            name.local,
+            local_name!("head")
+                | local_name!("template")
+                | local_name!("noscript")
+                | local_name!("script")
+                | local_name!("style")
+                | local_name!("title")
+        );
+
+    is_hidden_by_default
+        || attrs.iter().any(|(name, value)| match name.local {
+            local_name!("hidden") => true,
+            local_name!("style") => value.split(';').any(|declaration| {
+                declaration
+                    .split_once(':')
+                    .is_some_and(|(property, value)| {
+                        property.trim().eq_ignore_ascii_case("display")
+                            && value.trim().to_ascii_lowercase().starts_with("none")
+                    })
+            }),
+            _ => false,
+        })
+}
+
+// Elements laid out on their own line in pretty mode, other elements flow with the text
+fn is_block_element(name: &QualName) -> bool {
+    name.ns == ns!(html)
+        && matches!(
+            name.local,
+            local_name!("address")
+                | local_name!("article")
+                | local_name!("aside")
+                | local_name!("base")
+                | local_name!("blockquote")
+                | local_name!("body")
+                | local_name!("caption")
+                | local_name!("colgroup")
+                | local_name!("dd")
+                | local_name!("details")
+                | local_name!("dialog")
+                | local_name!("div")
+                | local_name!("dl")
+                | local_name!("dt")
+                | local_name!("fieldset")
+                | local_name!("figcaption")
+                | local_name!("figure")
+                | local_name!("footer")
+                | local_name!("form")
+                | local_name!("h1")
+                | local_name!("h2")
+                | local_name!("h3")
+                | local_name!("h4")
+                | local_name!("h5")
+                | local_name!("h6")
+                | local_name!("head")
+                | local_name!("header")
+                | local_name!("hr")
+                | local_name!("html")
+                | local_name!("li")
+                | local_name!("link")
+                | local_name!("listing")
+                | local_name!("main")
+                | local_name!("meta")
+                | local_name!("nav")
+                | local_name!("noscript")
+                | local_name!("ol")
+                | local_name!("p")
+                | local_name!("pre")
+                | local_name!("script")
+                | local_name!("section")
+                | local_name!("style")
+                | local_name!("summary")
+                | local_name!("table")
+                | local_name!("tbody")
+                | local_name!("template")
+                | local_name!("tfoot")
+                | local_name!("thead")
+                | local_name!("title")
+                | local_name!("tr")
+                | local_name!("ul")
+        )
+}
+
+// What follows an element whose end tag is pending
+enum Following<'a> {
+    Element(&'a LocalName),
+    ParentEnd(&'a QualName),
+    Content,
+}
+
+// The end tags which may be omitted, see https://html.spec.whatwg.org/multipage/syntax.html#optional-tags
+fn is_end_tag_optional(name: &LocalName) -> bool {
+    matches!(
+        *name,
+        local_name!("body")
+            | local_name!("dd")
+            | local_name!("dt")
+            | local_name!("head")
+            | local_name!("li")
+            | local_name!("optgroup")
+            | local_name!("option")
+            | local_name!("p")
+            | local_name!("rp")
+            | local_name!("rt")
+            | local_name!("tbody")
+            | local_name!("td")
+            | local_name!("tfoot")
+            | local_name!("th")
+            | local_name!("thead")
+            | local_name!("tr")
+    )
+}
+
+fn can_omit_end_tag(name: &LocalName, following: Following) -> bool {
+    match following {
+        Following::Element(next) => match *name {
+            local_name!("head") => true,
+            local_name!("li") => *next == local_name!("li"),
+            local_name!("dt") | local_name!("dd") => {
+                matches!(*next, local_name!("dt") | local_name!("dd"))
+            }
+            local_name!("rt") | local_name!("rp") => {
+                matches!(*next, local_name!("rt") | local_name!("rp"))
+            }
+            local_name!("optgroup") => matches!(*next, local_name!("optgroup") | local_name!("hr")),
+            local_name!("option") => matches!(
+                *next,
+                local_name!("option") | local_name!("optgroup") | local_name!("hr")
+            ),
+            local_name!("thead") | local_name!("tbody") => {
+                matches!(*next, local_name!("tbody") | local_name!("tfoot"))
+            }
+            local_name!("tr") => *next == local_name!("tr"),
+            local_name!("td") | local_name!("th") => {
+                matches!(*next, local_name!("td") | local_name!("th"))
+            }
+            local_name!("p") => matches!(
+                *next,
+                local_name!("address")
+                    | local_name!("article")
+                    | local_name!("aside")
+                    | local_name!("blockquote")
+                    | local_name!("details")
+                    | local_name!("dialog")
+                    | local_name!("div")
+                    | local_name!("dl")
+                    | local_name!("fieldset")
+                    | local_name!("figcaption")
+                    | local_name!("figure")
+                    | local_name!("footer")
+                    | local_name!("form")
+                    | local_name!("h1")
+                    | local_name!("h2")
+                    | local_name!("h3")
+                    | local_name!("h4")
+                    | local_name!("h5")
+                    | local_name!("h6")
+                    | local_name!("header")
+                    | local_name!("hgroup")
+                    | local_name!("hr")
+                    | local_name!("main")
+                    | local_name!("menu")
+                    | local_name!("nav")
+                    | local_name!("ol")
+                    | local_name!("p")
+                    | local_name!("pre")
+                    | local_name!("section")
+                    | local_name!("table")
+                    | local_name!("ul")
+            ),
+            _ => false,
+        },
+        // The end of a paragraph can't be implied by the end of an element which may contain flow content in a paragraph,
+        // nor by the end of a custom or a foreign element
+        Following::ParentEnd(parent) => match *name {
+            local_name!("p") => {
+                parent.ns == ns!(html)
+                    && !parent.local.contains('-')
+                    && !matches!(
+                        parent.local,
+                        local_name!("a")
+                            | local_name!("audio")
+                            | local_name!("del")
+                            | local_name!("ins")
+                            | local_name!("map")
+                            | local_name!("noscript")
+                            | local_name!("video")
+                    )
+            }
+            local_name!("dt") | local_name!("head") | local_name!("thead") => false,
+            _ => true,
+        },
+        Following::Content => false,
+    }
+}
+
+// Whitespaces surrounding these elements are not rendered by browsers
+fn is_whitespace_boundary(name: &QualName) -> bool {
+    name.ns == ns!(html)
+        && (matches!(
+            name.local,
+            local_name!("br")
+                | local_name!("option")
+                | local_name!("optgroup")
+                | local_name!("td")
+                | local_name!("th")
+                | local_name!("title")
+        ) || (is_block_element(name)
+            && !matches!(
+                name.local,
+                local_name!("base")
+                    | local_name!("link")
+                    | local_name!("meta")
+                    | local_name!("noscript")
+                    | local_name!("script")
+                    | local_name!("style")
+                    | local_name!("template")
+            )))
+}
+
+// The elements whose whitespace-only texts are dropped by browsers
+fn is_text_free_element(name: &LocalName) -> bool {
+    matches!(
+        *name,
+        local_name!("colgroup")
+            | local_name!("dl")
+            | local_name!("head")
+            | local_name!("html")
+            | local_name!("ol")
+            | local_name!("optgroup")
+            | local_name!("select")
+            | local_name!("table")
+            | local_name!("tbody")
+            | local_name!("tfoot")
+            | local_name!("thead")
+            | local_name!("tr")
+            | local_name!("ul")
+    )
+}
+
+fn is_boolean_attribute(name: &QualName, value: &str) -> bool {
+    name.ns == ns!()
+        && (value.is_empty() || value.eq_ignore_ascii_case(name.local.as_ref()))
+        && matches!(
+            name.local.as_ref(),
+            "allowfullscreen"
+                | "async"
+                | "autofocus"
+                | "autoplay"
+                | "checked"
+                | "controls"
+                | "default"
+                | "defer"
+                | "disabled"
+                | "formnovalidate"
+                | "hidden"
+                | "inert"
+                | "ismap"
+                | "itemscope"
+                | "loop"
+                | "multiple"
+                | "muted"
+                | "nomodule"
+                | "novalidate"
+                | "open"
+                | "playsinline"
+                | "readonly"
+                | "required"
+                | "reversed"
+                | "selected"
+        )
+}
+
+// An unquoted value ends at the first whitespace or ">", and can't contain characters mistaken for markup
+fn is_unquoted_value_allowed(value: &str) -> bool {
+    !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_ascii_whitespace() || matches!(c, '"' | '\'' | '=' | '<' | '>' | '`'))
+}
+
 fn tagname(name: &QualName) -> LocalName {
     match name.ns {
         ns!(html) | ns!(mathml) | ns!(svg) => (),
@@ -472,24 +2420,11 @@ fn tagname(name: &QualName) -> LocalName {
     name.local.clone()
 }
 
-// This struct allows to enable/disable the color
-// the library only expose an environment variable to do so
-#[derive(Clone, Copy)]
-pub struct Colorizer {
-    is_enabled: bool,
-}
-
-impl Colorizer {
-    fn new(is_enabled: bool) -> Self {
-        Colorizer { is_enabled }
-    }
-
-    fn colorize<T: AsRef<str>>(self, s: T, color: Color) -> String {
-        if self.is_enabled {
-            s.as_ref().color(color).to_string()
-        } else {
-            s.as_ref().to_string()
-        }
+// Names keep their namespace prefix in xml mode (e.g. atom:link)
+fn qualified_name(settings: &SerializeSettings, name: &QualName) -> String {
+    match &name.prefix {
+        Some(prefix) if settings.is_xml_enabled => format!("{}:{}", prefix, name.local),
+        _ => tagname(name).to_string(),
     }
 }
 
@@ -497,15 +2432,22 @@ impl Colorizer {
 mod tests {
     use pretty_assertions::assert_eq;
     use std::{env, fs};
+    use url::Url;
 
-    use crate::filter::filter;
+    use crate::filter::{filter, parse_html, parse_xml};
     use crate::parser::CssCombinator;
     use crate::renderer;
     use crate::renderer::SNode;
-    use crate::{parser::CssSelector, renderer::SerializeSettingsBuilder};
+    use crate::{
+        parser::CssSelector,
+        renderer::{AttributesFormat, JsonFormat, SerializeSettingsBuilder, TextMode},
+    };
 
     #[test]
     fn serialize_nodes() {
+        // The colored library disables colors when stdout is not a tty
+        colored::control::set_override(true);
+
         struct Scenario {
             filename: &'static str,
             selector: &'static str,
@@ -515,71 +2457,391 @@ mod tests {
 
         let scenarios: Vec<Scenario> = vec![
             Scenario {
-                // Remove all extra spaces
-                filename: "document_with_space",
-                selector: "html",
-                settings: (|| SerializeSettingsBuilder::new())(),
+                // Remove all extra spaces
+                filename: "document_with_space",
+                selector: "html",
+                settings: SerializeSettingsBuilder::new(),
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Extract one element per line
+                filename: "extract_markup",
+                selector: "div",
+                settings: SerializeSettingsBuilder::new(),
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Enable colors
+                filename: "enable_colors",
+                selector: "div",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.enable_color();
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Color JSON tokens with a theme
+                filename: "render_json_colors",
+                selector: "li",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.enable_color();
+                    s.render_json();
+                    s.set_theme(
+                        renderer::theme::parse_theme("key=cyan:null=245:punctuation=#808080")
+                            .unwrap(),
+                    );
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Text only
+                filename: "text_only",
+                selector: "html",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.should_render_text_only();
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Extract the texts which are direct children of the node
+                filename: "text_own",
+                selector: "article",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.should_render_text_only();
+                    s.set_text_mode(TextMode::Own);
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Extract the inner text, hidden content is left out and blocks start on a new line
+                filename: "text_inner",
+                selector: "article",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.should_render_text_only();
+                    s.set_text_mode(TextMode::Inner);
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Extract every text node on its own line
+                filename: "text_nodes",
+                selector: "article",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.should_render_text_only();
+                    s.render_text_node_per_line();
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Render attributes
+                filename: "render_attributes",
+                selector: "div",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.should_render_attributes(vec!["class".to_string(), "data-value".to_string()]);
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Render a whole html document in JSON
+                filename: "render_whole_json",
+                selector: "html",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.render_json();
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    let a: Vec<SNode> = serde_json::from_str(actual.as_str()).unwrap();
+                    let e: Vec<SNode> = serde_json::from_str(expected.as_str()).unwrap();
+                    assert_eq!(a, e);
+                }),
+            },
+            Scenario {
+                // Render part of html tree in a single JSON
+                filename: "render_partial_json",
+                selector: "div",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.render_json();
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    let a: Vec<SNode> = serde_json::from_str(actual.as_str()).unwrap();
+                    let e: Vec<SNode> = serde_json::from_str(expected.as_str()).unwrap();
+                    assert_eq!(a, e);
+                }),
+            },
+            Scenario {
+                // Render a whole html document in the JSON shape of pup
+                filename: "render_pup_json",
+                selector: "html",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.render_json();
+                    s.set_json_format(JsonFormat::Pup);
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    let a: serde_json::Value = serde_json::from_str(actual.as_str()).unwrap();
+                    let e: serde_json::Value = serde_json::from_str(expected.as_str()).unwrap();
+                    assert_eq!(a, e);
+                }),
+            },
+            Scenario {
+                // Render one JSON object per line in the shape of pup
+                filename: "render_pup_ndjson",
+                selector: "div",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.render_ndjson();
+                    s.set_json_format(JsonFormat::Pup);
+                    s.annotate_selector();
+                    s.set_selector("div".to_string());
+                    s
+                },
+                test: Box::new(|actual, expected| assert_eq!(actual, expected)),
+            },
+            Scenario {
+                // Render one JSON object per line keeping the texts in children nodes
+                filename: "render_lossless_ndjson",
+                selector: "div",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.render_ndjson();
+                    s.set_json_format(JsonFormat::Lossless);
+                    s
+                },
+                test: Box::new(|actual, expected| assert_eq!(actual, expected)),
+            },
+            Scenario {
+                // Render texts as children nodes to keep their order and their whitespaces
+                filename: "render_lossless_json",
+                selector: "body",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.render_json();
+                    s.set_json_format(JsonFormat::Lossless);
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    let a: Vec<SNode> = serde_json::from_str(actual.as_str()).unwrap();
+                    let e: Vec<SNode> = serde_json::from_str(expected.as_str()).unwrap();
+                    assert_eq!(a, e);
+                }),
+            },
+            Scenario {
+                // Render the JSON tree in yaml
+                filename: "render_yaml",
+                selector: "ul",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.render_yaml();
+                    s
+                },
                 test: Box::new(|actual, expected| {
                     assert_eq!(actual, expected);
                 }),
             },
             Scenario {
-                // Extract one element per line
-                filename: "extract_markup",
-                selector: "div",
-                settings: (|| SerializeSettingsBuilder::new())(),
+                // Render the JSON tree in toml leaving out null values, attributes without value are empty
+                filename: "render_toml",
+                selector: "ul",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.render_toml();
+                    s
+                },
                 test: Box::new(|actual, expected| {
                     assert_eq!(actual, expected);
                 }),
             },
             Scenario {
-                // Enable colors
-                filename: "enable_colors",
-                selector: "div",
-                settings: (|| {
+                // Render the extracted texts in yaml
+                filename: "render_yaml_texts",
+                selector: "li",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.render_yaml();
+                    s.should_render_text_only();
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Convert the nodes to markdown
+                filename: "render_markdown",
+                selector: "article",
+                settings: {
                     let mut s = SerializeSettingsBuilder::new();
                     s.enable_color();
+                    s.render_markdown();
+                    s.set_base_url(Url::parse("https://docs.example.com/").unwrap());
                     s
-                })(),
+                },
                 test: Box::new(|actual, expected| {
                     assert_eq!(actual, expected);
                 }),
             },
             Scenario {
-                // Text only
-                filename: "text_only",
-                selector: "html",
-                settings: (|| {
+                // Lay out the nodes as plain text wrapped to 40 columns
+                filename: "render_text",
+                selector: "article",
+                settings: {
                     let mut s = SerializeSettingsBuilder::new();
-                    s.should_render_text_only();
+                    s.enable_color();
+                    s.render_text();
+                    s.set_width(40);
+                    s.set_base_url(Url::parse("https://docs.example.com/").unwrap());
                     s
-                })(),
+                },
                 test: Box::new(|actual, expected| {
                     assert_eq!(actual, expected);
                 }),
             },
             Scenario {
-                // Render attributes
-                filename: "render_attributes",
+                // Lay out the nodes as plain text wrapped to a single column, a word is never split
+                filename: "render_text_narrow",
+                selector: "article",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.render_text();
+                    s.set_width(renderer::parse_width("1").unwrap());
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Print every node with a format string
+                filename: "render_format_string",
+                selector: "a",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.enable_color();
+                    s.annotate_source("links.html".to_string());
+                    s.set_selector("ul a".to_string());
+                    s.set_format_string(
+                        renderer::parse_format_string(
+                            r"{index} {name}\t{attr:href}\t{text}\t{{{inner_html}}} {source} {selector}",
+                        )
+                        .unwrap(),
+                    );
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Resolve urls against the base url and the <base href> element of the document
+                filename: "resolve_urls",
                 selector: "div",
-                settings: (|| {
+                settings: {
                     let mut s = SerializeSettingsBuilder::new();
-                    s.should_render_attributes(vec!["class".to_string(), "data-value".to_string()]);
+                    s.set_base_url(Url::parse("https://example.com/docs/page.html").unwrap());
                     s
-                })(),
+                },
                 test: Box::new(|actual, expected| {
                     assert_eq!(actual, expected);
                 }),
             },
             Scenario {
-                // Render a whole html document in JSON
-                filename: "render_whole_json",
-                selector: "html",
-                settings: (|| {
+                // Render the attributes of every node as a JSON object, a glob stands for the matching attributes
+                filename: "render_attribute_records_json",
+                selector: "a",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.should_render_attributes(vec![
+                        "href".to_string(),
+                        "title".to_string(),
+                        "data-*".to_string(),
+                    ]);
+                    s.set_attributes_format(AttributesFormat::Json);
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Render every attribute of every node as a tsv row
+                filename: "render_attribute_records_tsv",
+                selector: "a",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.should_render_attributes(vec!["*".to_string()]);
+                    s.set_attributes_format(AttributesFormat::Tsv);
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Resolve urls against the base url when rendering attributes
+                filename: "resolve_urls_attributes",
+                selector: "div",
+                settings: {
                     let mut s = SerializeSettingsBuilder::new();
+                    s.set_base_url(Url::parse("https://example.com/docs/page.html").unwrap());
+                    s.should_render_attributes(vec![
+                        "href".to_string(),
+                        "src".to_string(),
+                        "srcset".to_string(),
+                        "action".to_string(),
+                        "formaction".to_string(),
+                    ]);
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Resolve urls when rendering JSON
+                filename: "resolve_urls_json",
+                selector: "div",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.set_base_url(Url::parse("https://example.com/docs/page.html").unwrap());
                     s.render_json();
                     s
-                })(),
+                },
                 test: Box::new(|actual, expected| {
                     let a: Vec<SNode> = serde_json::from_str(actual.as_str()).unwrap();
                     let e: Vec<SNode> = serde_json::from_str(expected.as_str()).unwrap();
@@ -587,20 +2849,148 @@ mod tests {
                 }),
             },
             Scenario {
-                // Render part of html tree in a single JSON
-                filename: "render_partial_json",
+                // Render one JSON object per line annotated with the selector and the source
+                filename: "render_ndjson",
                 selector: "div",
-                settings: (|| {
+                settings: {
                     let mut s = SerializeSettingsBuilder::new();
-                    s.render_json();
+                    s.render_ndjson();
+                    s.annotate_selector();
+                    s.set_selector("div".to_string());
+                    s.annotate_source("render_ndjson".to_string());
                     s
-                })(),
+                },
                 test: Box::new(|actual, expected| {
-                    let a: Vec<SNode> = serde_json::from_str(actual.as_str()).unwrap();
-                    let e: Vec<SNode> = serde_json::from_str(expected.as_str()).unwrap();
+                    let a = actual
+                        .lines()
+                        .map(|line| serde_json::from_str(line).unwrap())
+                        .collect::<Vec<serde_json::Value>>();
+                    let e = expected
+                        .lines()
+                        .map(|line| serde_json::from_str(line).unwrap())
+                        .collect::<Vec<serde_json::Value>>();
                     assert_eq!(a, e);
                 }),
             },
+            Scenario {
+                // Keep every whitespace and leave urls as is
+                filename: "render_raw",
+                selector: "div",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.set_base_url(Url::parse("https://example.com/docs/page.html").unwrap());
+                    s.render_raw();
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Minify the markup without changing how browsers parse and lay it out
+                filename: "minify",
+                selector: "body",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.render_minified();
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Keep the attribute values byte for byte but trim the resolved urls
+                filename: "minify_attribute_values",
+                selector: "div",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.render_minified();
+                    s.set_base_url(Url::parse("https://example.com/").unwrap());
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Minify the markup and keep the comments
+                filename: "minify_keep_comments",
+                selector: "div",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.render_minified();
+                    s.keep_comments();
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Keep the new line starting the content of pre, textarea and listing elements
+                filename: "leading_new_line_raw",
+                selector: "div",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.render_raw();
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                filename: "leading_new_line_pretty",
+                selector: "div",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.set_indent(2);
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                filename: "leading_new_line_minify",
+                selector: "div",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.render_minified();
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Indent block-level elements and keep preformatted content intact
+                filename: "pretty_print",
+                selector: "div",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.set_indent(2);
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Indent with colors
+                filename: "pretty_print_colors",
+                selector: "ul",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.set_indent(4);
+                    s.enable_color();
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
         ];
 
         for s in scenarios {
@@ -616,8 +3006,9 @@ mod tests {
             )
             .unwrap();
 
+            let document = parse_html(&given_html);
             let nodes = filter(
-                given_html,
+                &document,
                 &vec![CssSelector {
                     name: Some(s.selector.to_string()),
                     attributes: vec![],
@@ -625,11 +3016,154 @@ mod tests {
                 }],
             );
 
-            let actual = renderer::serialize_nodes(s.settings, nodes)
+            // Like main, the base url is found once per document
+            let mut settings = s.settings;
+            settings.serialize_settings.base_url =
+                renderer::find_base_url(settings.serialize_settings.base_url.take(), &document);
+            let actual = renderer::serialize_nodes(settings, nodes)
                 .unwrap()
                 .replace("\u{1b}", "\\u{1b}");
 
             (s.test)(actual, expected);
         }
+
+        assert!(renderer::parse_width("0").is_err());
+        assert!(renderer::parse_width("-1").is_err());
+    }
+
+    #[test]
+    fn serialize_nodes_of_several_selectors() {
+        // Every selector is matched against the same document, which the rendering of the previous ones leaves intact
+        let given_html = br#"<p class="a"><b id="x">hi</b></p>"#;
+        let document = parse_html(given_html);
+        for format in [JsonFormat::Rup, JsonFormat::Pup, JsonFormat::Lossless] {
+            for name in ["p", "b", "p"] {
+                let render = |document| {
+                    let mut settings = SerializeSettingsBuilder::new();
+                    settings.render_json();
+                    settings.set_json_format(format);
+                    let nodes = filter(
+                        document,
+                        &vec![CssSelector {
+                            name: Some(name.to_string()),
+                            attributes: vec![],
+                            combinator: CssCombinator::Descendant,
+                        }],
+                    );
+                    renderer::serialize_nodes(settings, nodes).unwrap()
+                };
+
+                assert_eq!(render(&document), render(&parse_html(given_html)));
+            }
+        }
+    }
+
+    #[test]
+    fn serialize_xml_nodes() {
+        struct Scenario {
+            filename: &'static str,
+            selector: &'static str,
+            settings: SerializeSettingsBuilder,
+            test: Box<dyn Fn(String, String)>,
+        }
+
+        let scenarios: Vec<Scenario> = vec![
+            Scenario {
+                // Render well-formed xml with self-closed elements, CDATA sections and namespaces
+                filename: "render_xml",
+                selector: "channel",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.render_xml();
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    assert_eq!(actual, expected);
+                }),
+            },
+            Scenario {
+                // Render xml in JSON keeping case-sensitive and prefixed names
+                filename: "render_xml_json",
+                selector: "channel",
+                settings: {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.render_xml();
+                    s.render_json();
+                    s
+                },
+                test: Box::new(|actual, expected| {
+                    let a: Vec<SNode> = serde_json::from_str(actual.as_str()).unwrap();
+                    let e: Vec<SNode> = serde_json::from_str(expected.as_str()).unwrap();
+                    assert_eq!(a, e);
+                }),
+            },
+        ];
+
+        for s in scenarios {
+            let given_xml =
+                fs::read(env::var("CARGO_MANIFEST_DIR").unwrap() + "/src/renderer/" + s.filename)
+                    .unwrap();
+
+            let expected = fs::read_to_string(
+                env::var("CARGO_MANIFEST_DIR").unwrap()
+                    + "/src/renderer/"
+                    + s.filename
+                    + "_expected",
+            )
+            .unwrap();
+
+            let document = parse_xml(&given_xml);
+            let nodes = filter(
+                &document,
+                &vec![CssSelector {
+                    name: Some(s.selector.to_string()),
+                    attributes: vec![],
+                    combinator: CssCombinator::Descendant,
+                }],
+            );
+
+            let actual = renderer::serialize_nodes(s.settings, nodes).unwrap();
+
+            (s.test)(actual, expected);
+        }
+    }
+
+    #[test]
+    fn deserialize_nodes() {
+        let scenarios = vec![
+            (
+                // Render back the html of nodes in lossless JSON
+                "from_lossless_json",
+                {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.render_raw();
+                    s
+                },
+            ),
+            (
+                // Read a JSON object per line and ignore annotations, attributes keep their order
+                "from_ndjson",
+                SerializeSettingsBuilder::new(),
+            ),
+        ];
+
+        for (filename, settings) in scenarios {
+            let given_json =
+                fs::read(env::var("CARGO_MANIFEST_DIR").unwrap() + "/src/renderer/" + filename)
+                    .unwrap();
+
+            let expected = fs::read_to_string(
+                env::var("CARGO_MANIFEST_DIR").unwrap() + "/src/renderer/" + filename + "_expected",
+            )
+            .unwrap();
+
+            let document = renderer::deserialize_nodes(&given_json).unwrap();
+            let nodes = document.children.borrow().to_vec();
+            let actual = renderer::serialize_nodes(settings, nodes).unwrap();
+
+            assert_eq!(actual, expected);
+        }
+
+        assert!(renderer::deserialize_nodes(br#"[{"tag": "div"}]"#).is_err());
     }
 }
@@ -0,0 +1,237 @@
+use colored::*;
+
+// The parts of the output having their own color
+#[derive(Clone, Copy)]
+pub enum Style {
+    // Tag names and markup delimiters
+    Tag,
+    AttributeName,
+    AttributeValue,
+    // Comments, doctypes and processing instructions
+    Comment,
+    JsonKey,
+    JsonString,
+    JsonNumber,
+    JsonBoolean,
+    JsonNull,
+    // Brackets, braces, colons and commas
+    JsonPunctuation,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ThemeColor {
+    // A named color or a truecolor
+    Color(Color),
+    // A color of the 256-color palette
+    Fixed(u8),
+}
+
+// The color of every style, a style without color is written as is. JSON is colored like jq does
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Theme {
+    tag: Option<ThemeColor>,
+    attribute_name: Option<ThemeColor>,
+    attribute_value: Option<ThemeColor>,
+    comment: Option<ThemeColor>,
+    json_key: Option<ThemeColor>,
+    json_string: Option<ThemeColor>,
+    json_number: Option<ThemeColor>,
+    json_boolean: Option<ThemeColor>,
+    json_null: Option<ThemeColor>,
+    json_punctuation: Option<ThemeColor>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            tag: Some(ThemeColor::Color(Color::Magenta)),
+            attribute_name: Some(ThemeColor::Color(Color::Yellow)),
+            attribute_value: Some(ThemeColor::Color(Color::Green)),
+            comment: Some(ThemeColor::Color(Color::Blue)),
+            json_key: Some(ThemeColor::Color(Color::Blue)),
+            json_string: Some(ThemeColor::Color(Color::Green)),
+            json_number: None,
+            json_boolean: None,
+            json_null: Some(ThemeColor::Color(Color::BrightBlack)),
+            json_punctuation: None,
+        }
+    }
+}
+
+// A theme is a list of STYLE=COLOR separated with colons (e.g. tag=blue:value=208:key=#5f87ff), the styles
+// left out keep their default color. A color is a name (e.g. red, bright_red), a number of the 256-color
+// palette, a #rrggbb truecolor or none to write the style as is
+pub fn parse_theme(spec: &str) -> Result<Theme, String> {
+    let mut theme = Theme::default();
+
+    for entry in spec.split(':').filter(|entry| !entry.trim().is_empty()) {
+        let (style, color) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("{}: STYLE=COLOR is expected", entry))?;
+        let color = parse_theme_color(color.trim()).map_err(|e| format!("{}: {}", entry, e))?;
+        let slot = match style.trim() {
+            "tag" => &mut theme.tag,
+            "attribute" => &mut theme.attribute_name,
+            "value" => &mut theme.attribute_value,
+            "comment" => &mut theme.comment,
+            "key" => &mut theme.json_key,
+            "string" => &mut theme.json_string,
+            "number" => &mut theme.json_number,
+            "boolean" => &mut theme.json_boolean,
+            "null" => &mut theme.json_null,
+            "punctuation" => &mut theme.json_punctuation,
+            style => return Err(format!("{}: unknown style {}", entry, style)),
+        };
+        *slot = color;
+    }
+
+    Ok(theme)
+}
+
+fn parse_theme_color(color: &str) -> Result<Option<ThemeColor>, String> {
+    if let Some(hex) = color.strip_prefix('#') {
+        let component = |index: usize| {
+            hex.get(index..index + 2)
+                .and_then(|component| u8::from_str_radix(component, 16).ok())
+        };
+        return match (hex.len(), component(0), component(2), component(4)) {
+            (6, Some(r), Some(g), Some(b)) => {
+                Ok(Some(ThemeColor::Color(Color::TrueColor { r, g, b })))
+            }
+            _ => Err(format!("invalid truecolor {}", color)),
+        };
+    }
+    if let Ok(index) = color.parse::<u8>() {
+        return Ok(Some(ThemeColor::Fixed(index)));
+    }
+
+    Ok(Some(ThemeColor::Color(
+        match color.to_ascii_lowercase().replace('-', "_").as_str() {
+            "none" => return Ok(None),
+            "black" => Color::Black,
+            "red" => Color::Red,
+            "green" => Color::Green,
+            "yellow" => Color::Yellow,
+            "blue" => Color::Blue,
+            "magenta" => Color::Magenta,
+            "cyan" => Color::Cyan,
+            "white" => Color::White,
+            "bright_black" | "gray" | "grey" => Color::BrightBlack,
+            "bright_red" => Color::BrightRed,
+            "bright_green" => Color::BrightGreen,
+            "bright_yellow" => Color::BrightYellow,
+            "bright_blue" => Color::BrightBlue,
+            "bright_magenta" => Color::BrightMagenta,
+            "bright_cyan" => Color::BrightCyan,
+            "bright_white" => Color::BrightWhite,
+            _ => return Err(format!("unknown color {}", color)),
+        },
+    )))
+}
+
+// This struct allows to enable/disable the color
+// the library only expose an environment variable to do so
+#[derive(Clone, Copy)]
+pub struct Colorizer {
+    is_enabled: bool,
+    theme: Theme,
+}
+
+impl Colorizer {
+    pub fn new(is_enabled: bool, theme: Theme) -> Self {
+        Colorizer { is_enabled, theme }
+    }
+
+    pub fn colorize<T: AsRef<str>>(self, s: T, style: Style) -> String {
+        let color = match style {
+            Style::Tag => self.theme.tag,
+            Style::AttributeName => self.theme.attribute_name,
+            Style::AttributeValue => self.theme.attribute_value,
+            Style::Comment => self.theme.comment,
+            Style::JsonKey => self.theme.json_key,
+            Style::JsonString => self.theme.json_string,
+            Style::JsonNumber => self.theme.json_number,
+            Style::JsonBoolean => self.theme.json_boolean,
+            Style::JsonNull => self.theme.json_null,
+            Style::JsonPunctuation => self.theme.json_punctuation,
+        };
+        match color {
+            Some(ThemeColor::Color(color)) if self.is_enabled => {
+                s.as_ref().color(color).to_string()
+            }
+            // The colored library has no 256-color support
+            Some(ThemeColor::Fixed(index))
+                if self.is_enabled && colored::control::SHOULD_COLORIZE.should_colorize() =>
+            {
+                format!("\x1b[38;5;{}m{}\x1b[0m", index, s.as_ref())
+            }
+            _ => s.as_ref().to_string(),
+        }
+    }
+
+    // Colors the tokens of a JSON document, its layout is kept
+    pub fn colorize_json(self, json: &str) -> String {
+        if !self.is_enabled {
+            return json.to_string();
+        }
+
+        let mut output = String::new();
+        let mut chars = json.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            match c {
+                '"' => {
+                    let mut end = json.len();
+                    while let Some((index, c)) = chars.next() {
+                        match c {
+                            '\\' => {
+                                chars.next();
+                            }
+                            '"' => {
+                                end = index + 1;
+                                break;
+                            }
+                            _ => (),
+                        }
+                    }
+                    // A string followed by a colon is a key
+                    let is_key = json[end..].trim_start().starts_with(':');
+                    output.push_str(
+                        self.colorize(
+                            &json[start..end],
+                            if is_key {
+                                Style::JsonKey
+                            } else {
+                                Style::JsonString
+                            },
+                        )
+                        .as_str(),
+                    );
+                }
+                '{' | '}' | '[' | ']' | ':' | ',' => output.push_str(
+                    self.colorize(c.to_string(), Style::JsonPunctuation)
+                        .as_str(),
+                ),
+                c if c.is_whitespace() => output.push(c),
+                _ => {
+                    let mut end = json.len();
+                    while let Some((index, c)) = chars.peek() {
+                        if c.is_whitespace() || matches!(c, '{' | '}' | '[' | ']' | ':' | ',' | '"')
+                        {
+                            end = *index;
+                            break;
+                        }
+                        chars.next();
+                    }
+                    let token = &json[start..end];
+                    let style = match token {
+                        "true" | "false" => Style::JsonBoolean,
+                        "null" => Style::JsonNull,
+                        _ => Style::JsonNumber,
+                    };
+                    output.push_str(self.colorize(token, style).as_str());
+                }
+            }
+        }
+        output
+    }
+}
@@ -0,0 +1,500 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    rc::Rc,
+};
+
+use markup5ever_rcdom::{Handle, Node, NodeData};
+use serde::Deserialize;
+
+use crate::filter;
+use crate::parser::{self, CssSelector};
+use crate::renderer;
+use crate::transform::{self, Transformation};
+use crate::tree;
+
+// An allowlist of the elements and attributes kept in a document, elements are given as rup selectors, e.g.
+//
+//   elements = ["p", "br", "a", "em", "strong", "ul", "li", "img", "div.note"]
+//   removed_elements = ["script", "style"]
+//   url_schemes = ["http", "https", "mailto"]
+//
+//   [attributes]
+//   a = ["href", "title"]
+//   img = ["src", "alt"]
+//   "*" = ["class", "data-*"]
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    #[serde(default)]
+    elements: Vec<String>,
+    #[serde(default = "default_removed_elements")]
+    removed_elements: Vec<String>,
+    #[serde(default)]
+    attributes: BTreeMap<String, Vec<String>>,
+    #[serde(default = "default_url_schemes")]
+    url_schemes: Vec<String>,
+    #[serde(default)]
+    comments: bool,
+}
+
+fn default_removed_elements() -> Vec<String> {
+    ["script", "style", "template", "iframe", "object", "embed"]
+        .iter()
+        .map(|name| name.to_string())
+        .collect()
+}
+
+fn default_url_schemes() -> Vec<String> {
+    ["http", "https", "mailto"]
+        .iter()
+        .map(|scheme| scheme.to_string())
+        .collect()
+}
+
+// The policy once its selectors are parsed
+#[derive(Clone, Debug)]
+pub struct Policy {
+    // Elements kept with their allowed attributes
+    elements: Vec<Vec<CssSelector>>,
+    // Elements removed with their content, the other elements which aren't kept are replaced with their content
+    removed_elements: Vec<Vec<CssSelector>>,
+    // Attribute names or globs allowed on the elements matching the selectors
+    attributes: Vec<(Vec<Vec<CssSelector>>, Vec<String>)>,
+    url_schemes: Vec<String>,
+    is_keeping_comments: bool,
+}
+
+pub fn parse_policy(content: &str) -> Result<Policy, String> {
+    let policy: PolicyFile = toml::from_str(content).map_err(|e| e.message().to_string())?;
+    let parse_selectors = |selectors: &[String]| {
+        selectors
+            .iter()
+            .flat_map(|selectors| parser::parse_groups(selectors))
+            .collect::<Vec<Vec<CssSelector>>>()
+    };
+
+    Ok(Policy {
+        elements: parse_selectors(&policy.elements),
+        removed_elements: parse_selectors(&policy.removed_elements),
+        attributes: policy
+            .attributes
+            .iter()
+            .map(|(selectors, names)| (parser::parse_groups(selectors), names.to_owned()))
+            .collect(),
+        url_schemes: policy
+            .url_schemes
+            .iter()
+            .map(|scheme| scheme.trim_end_matches(':').to_lowercase())
+            .collect(),
+        is_keeping_comments: policy.comments,
+    })
+}
+
+// Represents something dropped from the document
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Removal {
+    Element(String),
+    UnwrappedElement(String),
+    Attribute {
+        element: String,
+        name: String,
+    },
+    Url {
+        element: String,
+        name: String,
+        scheme: String,
+    },
+    Comment,
+}
+
+// What the sanitization removed from a document, every kind of removal is counted
+#[derive(Debug, Default, PartialEq)]
+pub struct Report(BTreeMap<Removal, usize>);
+
+impl Report {
+    fn add(&mut self, removal: Removal) {
+        *self.0.entry(removal).or_default() += 1;
+    }
+}
+
+// One removal by line, e.g. removed attribute onclick of <p> x2
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines = self
+            .0
+            .iter()
+            .map(|(removal, count)| {
+                let removal = match removal {
+                    Removal::Element(element) => format!("removed element <{}>", element),
+                    Removal::UnwrappedElement(element) => {
+                        format!("unwrapped element <{}>", element)
+                    }
+                    Removal::Attribute { element, name } => {
+                        format!("removed attribute {} of <{}>", name, element)
+                    }
+                    Removal::Url {
+                        element,
+                        name,
+                        scheme,
+                    } => format!("removed {}: url in {} of <{}>", scheme, name, element),
+                    Removal::Comment => "removed comment".to_string(),
+                };
+                format!("{} x{}", removal, count)
+            })
+            .collect::<Vec<String>>();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+// Removes from the document every element, attribute and url the policy doesn't allow.
+// Event handler attributes (e.g. onclick), srcdoc attributes and script urls (e.g. javascript:) are always removed.
+// noscript elements are always removed with their content: it is parsed as text but a browser
+// with scripting disabled parses it as markup, which would then be left unsanitized.
+// The html, head and body elements of a document are kept whatever the policy since the html parser
+// adds them when they are missing, only their attributes and their content are sanitized
+pub fn sanitize(document: &Handle, policy: &Policy) -> Report {
+    let find = |selector_groups: &[Vec<CssSelector>]| {
+        selector_groups
+            .iter()
+            .flat_map(|selectors| filter::filter_nested(document, selectors))
+            .map(|node| Rc::as_ptr(&node))
+            .collect::<HashSet<*const Node>>()
+    };
+    let kept_elements = find(&policy.elements);
+    let removed_elements = find(&policy.removed_elements);
+    let mut allowed_attributes: HashMap<*const Node, Vec<&str>> = HashMap::new();
+    for (selector_groups, names) in policy.attributes.iter() {
+        for node in find(selector_groups) {
+            allowed_attributes
+                .entry(node)
+                .or_default()
+                .extend(names.iter().map(|name| name.as_str()));
+        }
+    }
+
+    let mut sanitizer = Sanitizer {
+        policy,
+        kept_elements,
+        removed_elements,
+        allowed_attributes,
+        report: Report::default(),
+    };
+    sanitizer.sanitize_children(document);
+    sanitizer.report
+}
+
+struct Sanitizer<'a> {
+    policy: &'a Policy,
+    kept_elements: HashSet<*const Node>,
+    removed_elements: HashSet<*const Node>,
+    allowed_attributes: HashMap<*const Node, Vec<&'a str>>,
+    report: Report,
+}
+
+impl<'a> Sanitizer<'a> {
+    // The children are copied first since they are moved or removed along the way
+    fn sanitize_children(&mut self, node: &Handle) {
+        let children = node.children.borrow().to_vec();
+        for child in children.iter() {
+            match document_element_name(child) {
+                Some(name) => {
+                    self.sanitize_children(child);
+                    self.sanitize_attributes(child, name);
+                }
+                None => self.sanitize_node(child),
+            }
+        }
+    }
+
+    fn sanitize_node(&mut self, node: &Handle) {
+        let name = match &node.data {
+            NodeData::Element { name, .. } => name.local.to_string(),
+            NodeData::Comment { .. } if !self.policy.is_keeping_comments => {
+                transform::transform_node(node, &Transformation::Delete);
+                self.report.add(Removal::Comment);
+                return;
+            }
+            _ => return,
+        };
+
+        let pointer = Rc::as_ptr(node);
+        if self.removed_elements.contains(&pointer) || name == "noscript" {
+            transform::transform_node(node, &Transformation::Delete);
+            self.report.add(Removal::Element(name));
+            return;
+        }
+
+        // The head holds no text, so its elements are removed rather than replaced with their content
+        if !self.kept_elements.contains(&pointer) && is_in_head(node) {
+            transform::transform_node(node, &Transformation::Delete);
+            self.report.add(Removal::Element(name));
+            return;
+        }
+
+        self.sanitize_children(node);
+        if !self.kept_elements.contains(&pointer) {
+            transform::transform_node(node, &Transformation::Unwrap);
+            self.report.add(Removal::UnwrappedElement(name));
+            return;
+        }
+
+        self.sanitize_attributes(node, &name);
+    }
+
+    fn sanitize_attributes(&mut self, node: &Handle, name: &str) {
+        let attrs = match &node.data {
+            NodeData::Element { attrs, .. } => attrs,
+            _ => return,
+        };
+
+        let allowed_attributes = self.allowed_attributes.get(&Rc::as_ptr(node));
+        // The content of a refresh directive holds the url the page is redirected to
+        let is_refresh = name == "meta"
+            && attrs.borrow().iter().any(|attr| {
+                attr.name.local.as_ref() == "http-equiv"
+                    && attr.value.trim().eq_ignore_ascii_case("refresh")
+            });
+        let mut removals = vec![];
+        attrs.borrow_mut().retain(|attr| {
+            let attribute_name = attr.name.local.as_ref();
+            // A srcdoc attribute holds a whole document, which could run scripts
+            let is_allowed = !attribute_name.starts_with("on")
+                && attribute_name != "srcdoc"
+                && allowed_attributes.is_some_and(|patterns| {
+                    patterns
+                        .iter()
+                        .any(|pattern| renderer::is_matching_attribute(pattern, attribute_name))
+                });
+            if !is_allowed {
+                removals.push(Removal::Attribute {
+                    element: name.to_owned(),
+                    name: attribute_name.to_string(),
+                });
+                return false;
+            }
+
+            match self.find_disallowed_scheme(attribute_name, &attr.value, is_refresh) {
+                Some(scheme) => {
+                    removals.push(Removal::Url {
+                        element: name.to_owned(),
+                        name: attribute_name.to_string(),
+                        scheme,
+                    });
+                    false
+                }
+                None => true,
+            }
+        });
+        for removal in removals {
+            self.report.add(removal);
+        }
+    }
+
+    // Relative urls are always allowed, a srcset or a style is disallowed as soon as one of its urls is
+    fn find_disallowed_scheme(&self, name: &str, value: &str, is_refresh: bool) -> Option<String> {
+        let urls = if name == "srcset" {
+            value
+                .split(',')
+                .filter_map(|candidate| candidate.split_whitespace().next())
+                .collect()
+        } else if name == "style" {
+            find_style_urls(value)
+        } else if name == "content" && is_refresh {
+            find_refresh_url(value).into_iter().collect()
+        } else if renderer::URL_ATTRIBUTES.contains(&name) {
+            vec![value]
+        } else {
+            vec![]
+        };
+
+        urls.into_iter()
+            .filter_map(find_scheme)
+            .find(|scheme| !self.policy.url_schemes.contains(scheme))
+    }
+}
+
+// The name of an html, head or body element making up the document, None for any other node
+fn document_element_name(node: &Handle) -> Option<&'static str> {
+    let name = match &node.data {
+        NodeData::Element { name, .. } => name.local.as_ref(),
+        _ => return None,
+    };
+    let parent = tree::get_parent(node)?;
+
+    match (name, &parent.data) {
+        ("html", NodeData::Document) => Some("html"),
+        ("head", _) if document_element_name(&parent) == Some("html") => Some("head"),
+        ("body", _) if document_element_name(&parent) == Some("html") => Some("body"),
+        _ => None,
+    }
+}
+
+fn is_in_head(node: &Handle) -> bool {
+    tree::get_parent(node).is_some_and(|parent| document_element_name(&parent) == Some("head"))
+}
+
+// The urls of the url() functions of a style attribute, e.g. background: url("image.png")
+fn find_style_urls(style: &str) -> Vec<&str> {
+    let lowercase_style = style.to_ascii_lowercase();
+    lowercase_style
+        .match_indices("url(")
+        .map(|(index, function)| {
+            let arguments = &style[index + function.len()..];
+            arguments[..arguments.find(')').unwrap_or(arguments.len())]
+                .trim()
+                .trim_matches(|c| c == '"' || c == '\'')
+        })
+        .collect()
+}
+
+// The url of a refresh directive, e.g. 5; url='https://example.com/'
+fn find_refresh_url(content: &str) -> Option<&str> {
+    let (_, url) = content.split_once([';', ','])?;
+    let url = url.trim_start();
+    let url = match url.get(..3) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("url") => {
+            url[3..].trim_start().strip_prefix('=').unwrap_or(url)
+        }
+        _ => url,
+    };
+    Some(url.trim().trim_matches(|c| c == '"' || c == '\''))
+}
+
+// Like browsers do, whitespaces and control characters are ignored (e.g. java\tscript:)
+fn find_scheme(url: &str) -> Option<String> {
+    let url = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>();
+    let (scheme, _) = url.split_once(':')?;
+    if scheme.is_empty()
+        || !scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        || !scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    {
+        return None;
+    }
+    Some(scheme.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use std::{env, fs};
+
+    use super::*;
+    use crate::filter::{parse_html, parse_html_fragment};
+    use crate::renderer::SerializeSettingsBuilder;
+
+    #[test]
+    fn sanitize_html() {
+        let scenarios = vec![
+            (
+                // Keep the allowed elements and attributes, unwrap the others and report them
+                false,
+                "comment.html",
+                "comment_policy.toml",
+                "comment_expected.html",
+                vec![
+                    "unwrapped element <div> x1",
+                    "unwrapped element <font> x1",
+                    "unwrapped element <span> x1",
+                    "removed element <iframe> x1",
+                    "removed element <script> x1",
+                    "removed attribute class of <p> x1",
+                    "removed attribute data-id of <p> x1",
+                    "removed attribute onclick of <a> x1",
+                    "removed attribute onerror of <img> x1",
+                    "removed attribute style of <p> x1",
+                    "removed javascript: url in href of <a> x2",
+                    "removed data: url in src of <img> x1",
+                    "removed comment x1",
+                ],
+            ),
+            (
+                // Allow attributes and elements with selectors and keep the comments
+                false,
+                "comment.html",
+                "selectors_policy.toml",
+                "selectors_expected.html",
+                vec![
+                    "unwrapped element <a> x3",
+                    "unwrapped element <b> x1",
+                    "unwrapped element <font> x1",
+                    "unwrapped element <img> x1",
+                    "unwrapped element <p> x1",
+                    "unwrapped element <span> x1",
+                    "removed element <iframe> x1",
+                    "removed element <script> x1",
+                ],
+            ),
+            (
+                // Keep the html, head and body elements of a document without reporting them
+                // and remove the elements of the head rather than unwrapping them
+                true,
+                "page.html",
+                "page_policy.toml",
+                "page_expected.html",
+                vec![
+                    "unwrapped element <b> x1",
+                    "removed element <script> x1",
+                    "removed attribute onload of <body> x1",
+                ],
+            ),
+            (
+                // Remove noscript elements, srcdoc attributes and the unsafe urls of styles and refresh
+                // directives even when every element and attribute is allowed
+                false,
+                "unsafe.html",
+                "unsafe_policy.toml",
+                "unsafe_expected.html",
+                vec![
+                    "removed element <noscript> x1",
+                    "removed attribute onerror of <img> x1",
+                    "removed attribute srcdoc of <iframe> x1",
+                    "removed javascript: url in content of <meta> x1",
+                    "removed javascript: url in style of <p> x1",
+                ],
+            ),
+        ];
+
+        let directory = env::var("CARGO_MANIFEST_DIR").unwrap() + "/src/sanitize/";
+        for (is_document, filename, policy_filename, expected_filename, expected_report) in
+            scenarios
+        {
+            let given_html = fs::read(directory.to_owned() + filename).unwrap();
+            let policy =
+                parse_policy(&fs::read_to_string(directory.to_owned() + policy_filename).unwrap())
+                    .unwrap();
+            let expected = fs::read_to_string(directory.to_owned() + expected_filename).unwrap();
+
+            let document = if is_document {
+                parse_html(&given_html)
+            } else {
+                parse_html_fragment(&given_html, "template")
+            };
+            let report = sanitize(&document, &policy);
+
+            let mut settings_builder = SerializeSettingsBuilder::new();
+            settings_builder.render_raw();
+            assert_eq!(
+                renderer::serialize_nodes(settings_builder, vec![document]).unwrap(),
+                expected
+            );
+            let mut report = report
+                .to_string()
+                .lines()
+                .map(|line| line.to_string())
+                .collect::<Vec<String>>();
+            let mut expected_report = expected_report
+                .into_iter()
+                .map(|line| line.to_string())
+                .collect::<Vec<String>>();
+            report.sort();
+            expected_report.sort();
+            assert_eq!(report, expected_report);
+        }
+    }
+}
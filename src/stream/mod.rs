@@ -0,0 +1,600 @@
+use std::{
+    cell::RefCell,
+    io::{self, Read, Write},
+};
+
+use encoding_rs::Encoding;
+use html5ever::tendril::StrTendril;
+use html5ever::tokenizer::{
+    states::RawKind, BufferQueue, Tag, TagKind, Token, TokenSink, TokenSinkResult, Tokenizer,
+    TokenizerOpts,
+};
+use html5ever::{local_name, namespace_url, ns, LocalName, QualName};
+use markup5ever_rcdom::{Handle, Node, NodeData};
+
+use crate::filter::{is_matching_selector_attributes, is_matching_selector_name};
+use crate::parser::{CssCombinator, CssSelector, CssSelectorAttribute, PseudoClass};
+use crate::renderer::{self, SerializeSettingsBuilder};
+use crate::tree;
+
+// Size of the chunks read from the input
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Only selectors that can be evaluated when a start tag is read are supported,
+// the siblings following an element are not known at that time
+pub fn check_selectors(selectors: &[CssSelector]) -> Result<(), String> {
+    selectors.iter().try_for_each(|selector| {
+        if selector.combinator == CssCombinator::AdjacentSibling {
+            return Err("the adjacent sibling combinator is not supported in streaming mode".to_string());
+        }
+        match selector.attributes.iter().find(|attribute| {
+            matches!(
+                attribute,
+                CssSelectorAttribute::PseudoClass(PseudoClass::LastChild)
+                    | CssSelectorAttribute::PseudoClass(PseudoClass::FirstOfType)
+            )
+        }) {
+            Some(_) => Err(
+                "the :last-child and :first-of-type pseudo classes are not supported in streaming mode"
+                    .to_string(),
+            ),
+            None => Ok(()),
+        }
+    })
+}
+
+// Filters the nodes matching the given css expression while the document is read,
+// every matched node is written to the output as soon as its end tag is found and freed afterwards.
+// The css expression is given along with its text, it is used to annotate the matched nodes.
+// Like in DOM mode, the descendants of a matched node are not searched and every selector of the chain
+// is matched by the first ancestor matching it
+pub fn filter(
+    reader: &mut dyn Read,
+    encoding: &'static Encoding,
+    selector_chain: (&str, &[CssSelector]),
+    settings_builder: SerializeSettingsBuilder,
+    output: &mut dyn Write,
+) -> io::Result<()> {
+    let sink = StreamSink {
+        selector_chain,
+        settings_builder,
+        output,
+        stack: vec![OpenElement {
+            name: LocalName::from(""),
+            index: Some(0),
+            children_count: 0,
+            node: None,
+        }],
+        matched_node: None,
+        written_count: 0,
+        error: None,
+    };
+    let mut tokenizer = Tokenizer::new(sink, TokenizerOpts::default());
+    let mut queue = BufferQueue::new();
+
+    // The decoder keeps the bytes of a character split between two chunks
+    let mut decoder = encoding.new_decoder();
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        let size = reader.read(&mut chunk)?;
+        let mut text = String::with_capacity(
+            decoder
+                .max_utf8_buffer_length(size)
+                .unwrap_or(CHUNK_SIZE * 4),
+        );
+        let _ = decoder.decode_to_string(&chunk[..size], &mut text, size == 0);
+
+        queue.push_back(StrTendril::from(text));
+        let _ = tokenizer.feed(&mut queue);
+
+        if let Some(e) = tokenizer.sink.error.take() {
+            return Err(e);
+        }
+        if size == 0 {
+            break;
+        }
+    }
+
+    tokenizer.end();
+    match tokenizer.sink.error.take() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+// Represents an element opened and not closed yet
+struct OpenElement {
+    name: LocalName,
+    // Index of the next selector of the chain to match, None when the descendants can't match anymore
+    index: Option<usize>,
+    // Number of element children read so far, it gives the position of the next child
+    children_count: usize,
+    // Defined when the element belongs to a matched subtree
+    node: Option<Handle>,
+}
+
+struct StreamSink<'a> {
+    selector_chain: (&'a str, &'a [CssSelector]),
+    settings_builder: SerializeSettingsBuilder,
+    output: &'a mut dyn Write,
+    stack: Vec<OpenElement>,
+    // The root of the subtree being built
+    matched_node: Option<Handle>,
+    // Number of matched nodes written so far, it gives the index of the next one
+    written_count: usize,
+    error: Option<io::Error>,
+}
+
+impl<'a> StreamSink<'a> {
+    fn start_tag(&mut self, tag: Tag, is_raw_text: bool) {
+        self.close_implied_elements(&tag.name);
+
+        let parent = self.stack.last_mut().unwrap();
+        let position = parent.children_count;
+        let parent_index = parent.index;
+        parent.children_count += 1;
+
+        let name = tag.name;
+        let attrs = RefCell::new(tag.attrs);
+        let (_, selectors) = self.selector_chain;
+        let mut is_matching_node = false;
+        let index = match (&self.matched_node, parent_index) {
+            (None, Some(index)) => selectors.get(index).and_then(|selector| {
+                // The length of the siblings is not known, :last-child is rejected beforehand
+                if is_matching_selector_name(selector, name.as_ref())
+                    && is_matching_selector_attributes(selector, &attrs, position, 0)
+                {
+                    is_matching_node = index + 1 == selectors.len();
+                    Some(index + 1)
+                } else if selector.combinator == CssCombinator::DirectChild {
+                    None
+                } else {
+                    Some(index)
+                }
+            }),
+            _ => None,
+        };
+
+        let node = if is_matching_node || self.matched_node.is_some() {
+            let node = Node::new(NodeData::Element {
+                name: QualName::new(None, ns!(html), name.clone()),
+                attrs,
+                template_contents: None,
+                mathml_annotation_xml_integration_point: false,
+            });
+            match self.stack.last().and_then(|parent| parent.node.as_ref()) {
+                Some(parent) => tree::append(parent, node.to_owned()),
+                None => self.matched_node = Some(node.to_owned()),
+            }
+            Some(node)
+        } else {
+            None
+        };
+
+        // Like in html, the self-closing flag is ignored for elements whose content is not markup
+        if (tag.self_closing && !is_raw_text) || is_void_element(&name) {
+            if is_matching_node {
+                self.write_matched_node();
+            }
+            return;
+        }
+
+        self.stack.push(OpenElement {
+            name,
+            index,
+            children_count: 0,
+            node,
+        });
+    }
+
+    fn end_tag(&mut self, tag: Tag) {
+        // Like a browser, an end tag closes every element opened after the matching start tag
+        // and is ignored when no such element is opened
+        if let Some(position) = self
+            .stack
+            .iter()
+            .skip(1)
+            .rposition(|element| element.name == tag.name)
+        {
+            self.close_elements(position + 1);
+        }
+    }
+
+    // Some end tags are optional, the start tag of an element closes the open elements which can't contain it
+    // like the html tree builder does
+    fn close_implied_elements(&mut self, name: &LocalName) {
+        if is_closing_p_element(name) {
+            self.close_last(
+                |open_name| *open_name == local_name!("p"),
+                |open_name| *open_name == local_name!("button") || is_scope_boundary(open_name),
+            );
+        }
+
+        match *name {
+            local_name!("li") => self.close_last(
+                |open_name| *open_name == local_name!("li"),
+                |open_name| {
+                    matches!(*open_name, local_name!("ol") | local_name!("ul"))
+                        || is_scope_boundary(open_name)
+                },
+            ),
+            local_name!("dt") | local_name!("dd") => self.close_last(
+                |open_name| matches!(*open_name, local_name!("dt") | local_name!("dd")),
+                |open_name| *open_name == local_name!("dl") || is_scope_boundary(open_name),
+            ),
+            local_name!("h1")
+            | local_name!("h2")
+            | local_name!("h3")
+            | local_name!("h4")
+            | local_name!("h5")
+            | local_name!("h6") => self.close_current(is_heading),
+            local_name!("td") | local_name!("th") => self.close_last(
+                |open_name| matches!(*open_name, local_name!("td") | local_name!("th")),
+                |open_name| matches!(*open_name, local_name!("tr") | local_name!("table")),
+            ),
+            local_name!("tr") => {
+                self.close_last(
+                    |open_name| matches!(*open_name, local_name!("td") | local_name!("th")),
+                    |open_name| matches!(*open_name, local_name!("tr") | local_name!("table")),
+                );
+                self.close_last(
+                    |open_name| *open_name == local_name!("tr"),
+                    is_table_section_boundary,
+                );
+            }
+            local_name!("option") => {
+                self.close_current(|open_name| *open_name == local_name!("option"))
+            }
+            local_name!("optgroup") => {
+                self.close_current(|open_name| *open_name == local_name!("option"));
+                self.close_current(|open_name| *open_name == local_name!("optgroup"));
+            }
+            _ => (),
+        }
+    }
+
+    // Closes the last open element matching is_closed unless an element matching is_boundary was opened after it
+    fn close_last(
+        &mut self,
+        is_closed: impl Fn(&LocalName) -> bool,
+        is_boundary: impl Fn(&LocalName) -> bool,
+    ) {
+        if let Some(position) = self
+            .stack
+            .iter()
+            .enumerate()
+            .skip(1)
+            .rev()
+            .take_while(|(_, element)| !is_boundary(&element.name))
+            .find(|(_, element)| is_closed(&element.name))
+            .map(|(position, _)| position)
+        {
+            self.close_elements(position);
+        }
+    }
+
+    // Closes the element opened last when it matches is_closed
+    fn close_current(&mut self, is_closed: impl Fn(&LocalName) -> bool) {
+        if self.stack.len() > 1 && is_closed(&self.stack.last().unwrap().name) {
+            self.close_elements(self.stack.len() - 1);
+        }
+    }
+
+    // Closes the open element at the given position of the stack and every element opened after it
+    fn close_elements(&mut self, position: usize) {
+        self.stack.truncate(position);
+        if self.stack.iter().all(|element| element.node.is_none()) {
+            self.write_matched_node();
+        }
+    }
+
+    fn append_child(&mut self, data: NodeData) {
+        if let Some(parent) = self.stack.last().and_then(|parent| parent.node.as_ref()) {
+            tree::append(parent, Node::new(data));
+        }
+    }
+
+    // The tokenizer may split a text in several tokens, they are merged like the tree builder does
+    fn append_text(&mut self, text: StrTendril) {
+        if let Some(parent) = self.stack.last().and_then(|parent| parent.node.as_ref()) {
+            if let Some(NodeData::Text { contents }) =
+                parent.children.borrow().last().map(|child| &child.data)
+            {
+                contents.borrow_mut().push_tendril(&text);
+                return;
+            }
+            tree::append(
+                parent,
+                Node::new(NodeData::Text {
+                    contents: RefCell::new(text),
+                }),
+            );
+        }
+    }
+
+    fn write_matched_node(&mut self) {
+        if let Some(node) = self.matched_node.take() {
+            let mut settings_builder = self.settings_builder.clone();
+            settings_builder.set_selector(self.selector_chain.0.to_string());
+            settings_builder.set_first_index(self.written_count);
+            self.written_count += 1;
+            // Like in DOM mode, a node rendering nothing doesn't output an empty line
+            let result = renderer::write_nodes(settings_builder, vec![node], self.output).and_then(
+                |is_written| {
+                    if is_written {
+                        writeln!(self.output)
+                    } else {
+                        Ok(())
+                    }
+                },
+            );
+            if let Err(e) = result {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+impl<'a> TokenSink for StreamSink<'a> {
+    type Handle = ();
+
+    fn process_token(&mut self, token: Token, _line_number: u64) -> TokenSinkResult<()> {
+        match token {
+            Token::TagToken(tag) if tag.kind == TagKind::StartTag => {
+                // The tree builder usually switches the tokenizer state for elements whose content is not markup
+                let result = match tag.name {
+                    local_name!("script") => TokenSinkResult::RawData(RawKind::ScriptData),
+                    local_name!("style")
+                    | local_name!("xmp")
+                    | local_name!("iframe")
+                    | local_name!("noembed")
+                    | local_name!("noframes") => TokenSinkResult::RawData(RawKind::Rawtext),
+                    local_name!("title") | local_name!("textarea") => {
+                        TokenSinkResult::RawData(RawKind::Rcdata)
+                    }
+                    local_name!("plaintext") => TokenSinkResult::Plaintext,
+                    _ => TokenSinkResult::Continue,
+                };
+                self.start_tag(tag, !matches!(result, TokenSinkResult::Continue));
+                return result;
+            }
+            Token::TagToken(tag) => self.end_tag(tag),
+            Token::CharacterTokens(contents) => self.append_text(contents),
+            Token::CommentToken(contents) => self.append_child(NodeData::Comment { contents }),
+            // A node not closed at the end of the document is written as is
+            Token::EOFToken => {
+                self.stack.truncate(1);
+                self.write_matched_node();
+            }
+            _ => (),
+        }
+        TokenSinkResult::Continue
+    }
+}
+
+// The start tags closing an open p element
+fn is_closing_p_element(name: &LocalName) -> bool {
+    is_heading(name)
+        || matches!(
+            *name,
+            local_name!("address")
+                | local_name!("article")
+                | local_name!("aside")
+                | local_name!("blockquote")
+                | local_name!("center")
+                | local_name!("dd")
+                | local_name!("details")
+                | local_name!("dialog")
+                | local_name!("dir")
+                | local_name!("div")
+                | local_name!("dl")
+                | local_name!("dt")
+                | local_name!("fieldset")
+                | local_name!("figcaption")
+                | local_name!("figure")
+                | local_name!("footer")
+                | local_name!("form")
+                | local_name!("header")
+                | local_name!("hgroup")
+                | local_name!("hr")
+                | local_name!("li")
+                | local_name!("listing")
+                | local_name!("main")
+                | local_name!("menu")
+                | local_name!("nav")
+                | local_name!("ol")
+                | local_name!("p")
+                | local_name!("plaintext")
+                | local_name!("pre")
+                | local_name!("section")
+                | local_name!("summary")
+                | local_name!("table")
+                | local_name!("ul")
+                | local_name!("xmp")
+        )
+}
+
+fn is_heading(name: &LocalName) -> bool {
+    matches!(
+        *name,
+        local_name!("h1")
+            | local_name!("h2")
+            | local_name!("h3")
+            | local_name!("h4")
+            | local_name!("h5")
+            | local_name!("h6")
+    )
+}
+
+// The elements hiding the elements opened before them from the start tags closing an element
+fn is_scope_boundary(name: &LocalName) -> bool {
+    matches!(
+        *name,
+        local_name!("applet")
+            | local_name!("caption")
+            | local_name!("html")
+            | local_name!("marquee")
+            | local_name!("object")
+            | local_name!("table")
+            | local_name!("td")
+            | local_name!("template")
+            | local_name!("th")
+    )
+}
+
+fn is_table_section_boundary(name: &LocalName) -> bool {
+    matches!(
+        *name,
+        local_name!("table") | local_name!("tbody") | local_name!("tfoot") | local_name!("thead")
+    )
+}
+
+fn is_void_element(name: &LocalName) -> bool {
+    matches!(
+        *name,
+        local_name!("area")
+            | local_name!("base")
+            | local_name!("br")
+            | local_name!("col")
+            | local_name!("embed")
+            | local_name!("hr")
+            | local_name!("img")
+            | local_name!("input")
+            | local_name!("keygen")
+            | local_name!("link")
+            | local_name!("meta")
+            | local_name!("param")
+            | local_name!("source")
+            | local_name!("track")
+            | local_name!("wbr")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{filter, parser};
+    use std::{env, fs};
+
+    // Reads a single byte at a time so every multi-byte character and every tag is split between chunks
+    struct ByteReader(Vec<u8>, usize);
+
+    impl Read for ByteReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.get(self.1) {
+                Some(byte) if !buf.is_empty() => {
+                    buf[0] = *byte;
+                    self.1 += 1;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn filter_streams() {
+        let scenarios = vec![
+            (
+                "tr.sold-out td",
+                "large_table.html",
+                "<td>Chocolat</td>\n<td>3.00</td>\n",
+            ),
+            (
+                // Comments kept in matched nodes and no tbody added
+                "table",
+                "large_table.html",
+                "<table id=\"prices\"><tr><th>Product</th><th>Price</th></tr><tr class=\"item\"><td>Café</td><td>1.20</td></tr><tr class=\"item\"><td>Thé<br>vert</td><td>2.10</td></tr><!--discontinued--><tr class=\"item sold-out\"><td>Chocolat</td><td>3.00</td></tr><tr class=\"item\"><td>Crème</td><td>0.50</td></tr></table>\n",
+            ),
+            ("#prices > tr.item:first-child", "large_table.html", ""),
+            ("br", "large_table.html", "<br>\n"),
+            (
+                // Elements closed by an end tag of an ancestor
+                "tr:nth-child(5) td",
+                "large_table.html",
+                "<td>Crème</td>\n<td>0.50</td>\n",
+            ),
+            ("li", "large_table.html", "<li>one</li>\n<li>two</li>\n"),
+            (
+                // Markup found in raw text elements is not parsed
+                "script",
+                "large_table.html",
+                "<script>if (a < b) { document.write(\"<td>not a cell</td>\"); }</script>\n",
+            ),
+            ("head td", "large_table.html", ""),
+            (
+                // The descendants of a matched node are not searched
+                "div",
+                "nested.html",
+                "<div class=\"outer\"><div class=\"inner\"><p>first</p></div><p>second</p></div>\n<div class=\"a\"><p>third</p></div>\n<div><section><p>fourth</p></section></div>\n",
+            ),
+            ("div.inner p", "nested.html", "<p>first</p>\n"),
+            ("div > p", "nested.html", "<p>second</p>\n<p>third</p>\n"),
+            // Selectors are matched by the first ancestor matching them like in DOM mode
+            ("section > p", "nested.html", ""),
+            ("section div p", "nested.html", "<p>third</p>\n<p>fourth</p>\n"),
+        ];
+
+        for (selector_chain, filename, expected) in scenarios {
+            let selectors = parser::parse(selector_chain.to_string());
+            let content =
+                fs::read(env::var("CARGO_MANIFEST_DIR").unwrap() + "/src/stream/" + filename)
+                    .unwrap();
+
+            for reader in [
+                Box::new(content.as_slice()) as Box<dyn Read>,
+                Box::new(ByteReader(content.to_owned(), 0)),
+            ] {
+                let mut reader = reader;
+                let mut output = vec![];
+                filter(
+                    &mut reader,
+                    encoding_rs::UTF_8,
+                    (selector_chain, &selectors),
+                    SerializeSettingsBuilder::new(),
+                    &mut output,
+                )
+                .unwrap();
+                assert_eq!(String::from_utf8(output).unwrap(), expected);
+            }
+        }
+
+        // Elements whose end tag is omitted are closed like in DOM mode
+        let scenarios = vec![
+            ("<p>a<div>b</div>", "p"),
+            ("<p>x<h1>y</h1>", "p"),
+            ("<p>a<span>b<div>c</div></span>", "p"),
+            ("<h1>a<h2>b</h2>", "h1"),
+            ("<select><option>1<optgroup><option>2</select>", "option"),
+            ("<ul><li>a<ul><li>b<li>c</ul><li>d</ul>", "li"),
+            ("<dl><dt>a<dd>b<dt>c</dl>", "dt"),
+            ("<div><button><p>a<p>b</button></div>", "p"),
+            ("<div>a</div>", "span"),
+        ];
+
+        for (content, selector_chain) in scenarios {
+            let selectors = parser::parse(selector_chain.to_string());
+            let mut output = vec![];
+            filter(
+                &mut content.as_bytes(),
+                encoding_rs::UTF_8,
+                (selector_chain, &selectors),
+                SerializeSettingsBuilder::new(),
+                &mut output,
+            )
+            .unwrap();
+
+            let mut expected = String::new();
+            for node in filter::filter(&filter::parse_html(content.as_bytes()), &selectors) {
+                expected += &renderer::serialize_nodes(SerializeSettingsBuilder::new(), vec![node])
+                    .unwrap();
+                expected += "\n";
+            }
+            assert_eq!(String::from_utf8(output).unwrap(), expected, "{}", content);
+        }
+
+        assert!(check_selectors(&parser::parse("ul li + li".to_string())).is_err());
+        assert!(check_selectors(&parser::parse("ul li:last-child".to_string())).is_err());
+        assert!(check_selectors(&parser::parse("ul > li:nth-child(2n+1)".to_string())).is_ok());
+    }
+}
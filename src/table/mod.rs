@@ -0,0 +1,373 @@
+use std::{io, rc::Rc};
+
+use html5ever::local_name;
+use markup5ever_rcdom::{Handle, Node, NodeData};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+use crate::renderer::{self, SerializeSettings, SerializeSettingsBuilder};
+
+// Limits from the html specification, they avoid building huge grids out of broken documents
+const MAX_COLSPAN: usize = 1000;
+const MAX_ROWSPAN: usize = 65534;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TableFormat {
+    Csv,
+    Tsv,
+    // Every row is a JSON object keyed by the header cells
+    Json,
+}
+
+// Represents a table once every spanned cell is copied in each row and column it covers
+#[derive(Debug, PartialEq)]
+pub struct Table {
+    // Empty when the table has no header row
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+// Extracts the tables among the nodes and renders them, the nodes which are not tables are ignored.
+// Csv and tsv tables are separated with an empty line, the rows of every table are gathered in a single JSON array
+pub fn serialize_tables(format: TableFormat, nodes: &[Rc<Node>]) -> io::Result<String> {
+    // The text of a cell is extracted like with --keep-text-only
+    let mut settings_builder = SerializeSettingsBuilder::new();
+    settings_builder.should_render_text_only();
+    let settings = settings_builder.build();
+
+    let tables = nodes
+        .iter()
+        .filter(|node| is_element(node, &local_name!("table")))
+        .map(|table| extract_table(table, &settings))
+        .collect::<io::Result<Vec<Table>>>()?;
+
+    match format {
+        TableFormat::Json => {
+            let records = tables
+                .iter()
+                .flat_map(|table| {
+                    let headers = json_keys(table);
+                    table
+                        .rows
+                        .iter()
+                        .map(move |row| Record(headers.to_owned(), row))
+                })
+                .collect::<Vec<Record>>();
+            serde_json::to_string(&records).map_err(io::Error::other)
+        }
+        _ => Ok(tables
+            .iter()
+            .map(|table| write_delimited(format, table))
+            .collect::<io::Result<Vec<String>>>()?
+            .join("\n\n")),
+    }
+}
+
+// Builds the grid of a table, rows of thead come first and rows of tfoot come last like a browser displays them.
+// The header is made of the rows of thead, or of the first rows made of th cells only when there is no thead
+pub fn extract_table(table: &Handle, settings: &SerializeSettings) -> io::Result<Table> {
+    let mut head_groups = vec![];
+    let mut body_groups = vec![];
+    let mut foot_groups = vec![];
+    // Rows directly under the table are gathered with the following ones in an implicit tbody
+    let mut rows = vec![];
+    for child in table.children.borrow().iter() {
+        match &child.data {
+            NodeData::Element { name, .. } if name.local == local_name!("tr") => {
+                rows.push(child.to_owned());
+            }
+            NodeData::Element { name, .. }
+                if matches!(
+                    name.local,
+                    local_name!("thead") | local_name!("tbody") | local_name!("tfoot")
+                ) =>
+            {
+                if !rows.is_empty() {
+                    body_groups.push(std::mem::take(&mut rows));
+                }
+                let group = element_children(child, &local_name!("tr"));
+                match name.local {
+                    local_name!("thead") => head_groups.push(group),
+                    local_name!("tfoot") => foot_groups.push(group),
+                    _ => body_groups.push(group),
+                }
+            }
+            _ => (),
+        }
+    }
+    if !rows.is_empty() {
+        body_groups.push(rows);
+    }
+
+    let mut header_grid = vec![];
+    for group in head_groups.iter() {
+        header_grid.append(&mut build_grid(group, settings)?);
+    }
+    let mut grid = vec![];
+    for group in body_groups.iter().chain(foot_groups.iter()) {
+        grid.append(&mut build_grid(group, settings)?);
+    }
+
+    // Without thead, the first rows holding th cells only are the header
+    if head_groups.is_empty() {
+        let header_length = body_groups.first().map_or(0, |group| {
+            group
+                .iter()
+                .take_while(|row| {
+                    let cells = cells(row);
+                    !cells.is_empty()
+                        && cells
+                            .iter()
+                            .all(|cell| is_element(cell, &local_name!("th")))
+                })
+                .count()
+        });
+        header_grid = grid.drain(..header_length).collect();
+    }
+
+    let width = header_grid
+        .iter()
+        .chain(grid.iter())
+        .map(|row| row.len())
+        .max()
+        .unwrap_or(0);
+
+    Ok(Table {
+        headers: if header_grid.is_empty() {
+            vec![]
+        } else {
+            (0..width)
+                .map(|column| merge_header_cells(&header_grid, column))
+                .collect()
+        },
+        rows: grid
+            .into_iter()
+            .map(|row| {
+                let mut row = row
+                    .into_iter()
+                    .map(|cell| cell.unwrap_or_default())
+                    .collect::<Vec<String>>();
+                row.resize(width, String::new());
+                row
+            })
+            .collect(),
+    })
+}
+
+// A cell spanning several rows or columns is copied in every slot it covers,
+// a rowspan can't go further than the group of rows of the cell
+fn build_grid(
+    rows: &[Handle],
+    settings: &SerializeSettings,
+) -> io::Result<Vec<Vec<Option<String>>>> {
+    let mut grid: Vec<Vec<Option<String>>> = vec![vec![]; rows.len()];
+
+    for (y, row) in rows.iter().enumerate() {
+        let mut x = 0;
+        for cell in cells(row) {
+            while grid[y].get(x).is_some_and(|slot| slot.is_some()) {
+                x += 1;
+            }
+
+            let colspan = span(&cell, "colspan", MAX_COLSPAN).max(1);
+            // A rowspan of 0 spans every remaining row of the group
+            let rowspan = match span(&cell, "rowspan", MAX_ROWSPAN) {
+                0 => rows.len() - y,
+                rowspan => rowspan.min(rows.len() - y),
+            };
+            let text = renderer::serialize_node(settings, &cell)?.unwrap_or_default();
+
+            for slots in grid.iter_mut().skip(y).take(rowspan) {
+                if slots.len() < x + colspan {
+                    slots.resize(x + colspan, None);
+                }
+                for slot in slots.iter_mut().skip(x).take(colspan) {
+                    *slot = Some(text.to_owned());
+                }
+            }
+            x += colspan;
+        }
+    }
+
+    Ok(grid)
+}
+
+// Header rows are merged in a single row, the values of a column are separated with a space
+// and a value repeated by a span is only kept once
+fn merge_header_cells(header_grid: &[Vec<Option<String>>], column: usize) -> String {
+    let mut values: Vec<String> = vec![];
+    for row in header_grid {
+        if let Some(Some(value)) = row.get(column) {
+            if !value.is_empty() && values.last() != Some(value) {
+                values.push(value.to_owned());
+            }
+        }
+    }
+    values.join(" ")
+}
+
+fn span(cell: &Handle, name: &str, max: usize) -> usize {
+    match &cell.data {
+        NodeData::Element { attrs, .. } => attrs
+            .borrow()
+            .iter()
+            .find(|attr| attr.name.local.as_ref() == name)
+            .and_then(|attr| attr.value.trim().parse::<usize>().ok())
+            .map_or(1, |span| span.min(max)),
+        _ => 1,
+    }
+}
+
+fn cells(row: &Handle) -> Vec<Handle> {
+    row.children
+        .borrow()
+        .iter()
+        .filter(|child| {
+            is_element(child, &local_name!("td")) || is_element(child, &local_name!("th"))
+        })
+        .cloned()
+        .collect()
+}
+
+fn element_children(node: &Handle, name: &html5ever::LocalName) -> Vec<Handle> {
+    node.children
+        .borrow()
+        .iter()
+        .filter(|child| is_element(child, name))
+        .cloned()
+        .collect()
+}
+
+fn is_element(node: &Handle, local_name: &html5ever::LocalName) -> bool {
+    matches!(&node.data, NodeData::Element { name, .. } if &name.local == local_name)
+}
+
+fn write_delimited(format: TableFormat, table: &Table) -> io::Result<String> {
+    let mut writer = csv::WriterBuilder::new();
+    // Tsv has no quoting, tabs and new lines are replaced with a space instead
+    if format == TableFormat::Tsv {
+        writer.delimiter(b'\t').quote_style(csv::QuoteStyle::Never);
+    }
+    let mut writer = writer.from_writer(vec![]);
+
+    for row in std::iter::once(&table.headers)
+        .filter(|headers| !headers.is_empty())
+        .chain(table.rows.iter())
+    {
+        let row = row.iter().map(|value| match format {
+            TableFormat::Tsv => value.replace(['\t', '\n', '\r'], " "),
+            _ => value.to_owned(),
+        });
+        writer.write_record(row)?;
+    }
+
+    // The line terminator of the last row is left to the caller
+    let content = writer.into_inner().map_err(|e| e.into_error())?;
+    let content = String::from_utf8_lossy(&content);
+    Ok(content.strip_suffix('\n').unwrap_or(&content).to_string())
+}
+
+// Without header the columns are numbered from 1, a header found several times is numbered from its second occurrence
+fn json_keys(table: &Table) -> Vec<String> {
+    let width = table
+        .rows
+        .first()
+        .map_or(table.headers.len(), |row| row.len());
+    if table.headers.is_empty() {
+        return (1..=width).map(|column| column.to_string()).collect();
+    }
+
+    let mut keys: Vec<String> = vec![];
+    for header in table.headers.iter() {
+        let mut key = header.to_owned();
+        let mut occurrence = 1;
+        while keys.contains(&key) {
+            occurrence += 1;
+            key = format!("{} {}", header, occurrence);
+        }
+        keys.push(key);
+    }
+    keys
+}
+
+// A row rendered as a JSON object, the properties keep the order of the columns
+struct Record<'a>(Vec<String>, &'a Vec<String>);
+
+impl Serialize for Record<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.1.len()))?;
+        for (key, value) in self.0.iter().zip(self.1.iter()) {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use std::{env, fs};
+
+    use super::*;
+    use crate::filter::{filter, parse_html};
+    use crate::parser;
+
+    #[test]
+    fn serialize_html_tables() {
+        let scenarios = vec![
+            (
+                // Expand rowspan and colspan and merge several header rows
+                "spanned_cells.html",
+                "table",
+                TableFormat::Csv,
+            ),
+            (
+                // Keep thead, tbody and tfoot in display order
+                "table_sections.html",
+                "table",
+                TableFormat::Tsv,
+            ),
+            (
+                // Key the records with the header cells
+                "table_sections.html",
+                "table",
+                TableFormat::Json,
+            ),
+            (
+                // Number the columns of a table without header and separate tables
+                "several_tables.html",
+                "table",
+                TableFormat::Json,
+            ),
+            (
+                // Separate the tables with an empty line
+                "several_tables.html",
+                "table",
+                TableFormat::Csv,
+            ),
+        ];
+
+        for (filename, selector, format) in scenarios {
+            let given_html =
+                fs::read(env::var("CARGO_MANIFEST_DIR").unwrap() + "/src/table/" + filename)
+                    .unwrap();
+            let extension = match format {
+                TableFormat::Csv => "csv",
+                TableFormat::Tsv => "tsv",
+                TableFormat::Json => "json",
+            };
+            let expected = fs::read_to_string(
+                env::var("CARGO_MANIFEST_DIR").unwrap()
+                    + "/src/table/"
+                    + filename.trim_end_matches(".html")
+                    + "_expected."
+                    + extension,
+            )
+            .unwrap();
+
+            let document = parse_html(&given_html);
+            let nodes = filter(&document, &parser::parse(selector.to_string()));
+
+            assert_eq!(serialize_tables(format, &nodes).unwrap(), expected);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::io;
+
+use markup5ever_rcdom::Handle;
+use serde_json::{Map, Value};
+
+use crate::filter;
+use crate::parser::{self, CssSelector};
+use crate::renderer::{self, SerializeSettings, SerializeSettingsBuilder};
+
+// What is extracted from a matched node
+#[derive(Debug, PartialEq)]
+enum Extraction {
+    // Extracted like with --keep-text-only
+    Text,
+    // The value of an attribute of the node, null when the node doesn't have the attribute
+    Attribute(String),
+    // The markup of the node
+    Html,
+    // The number of matched nodes
+    Count,
+}
+
+#[derive(Debug, PartialEq)]
+enum Field {
+    // The value extracted from the first matched node or from every matched node when is_list is set
+    Value {
+        selectors: Vec<CssSelector>,
+        extraction: Extraction,
+        is_list: bool,
+    },
+    // A record extracted from every matched node, the selectors of its fields are scoped to the node
+    Each {
+        selectors: Vec<CssSelector>,
+        template: Template,
+    },
+}
+
+// Maps field names to the data to extract, e.g. {"name": "h2 | text", "link": "a | attr:href"}
+#[derive(Debug, PartialEq)]
+pub struct Template {
+    fields: Vec<(String, Field)>,
+}
+
+// A field is either "SELECTOR | MODE..." where modes are text (by default), attr:NAME, html, count and list,
+// or {"selector": SELECTOR, "each": TEMPLATE} to extract a record from every matched node
+pub fn parse_template(content: &str) -> Result<Template, String> {
+    let value: Value = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    parse_template_value(&value)
+}
+
+fn parse_template_value(value: &Value) -> Result<Template, String> {
+    let fields = value
+        .as_object()
+        .ok_or_else(|| "a template must be a JSON object".to_string())?;
+
+    Ok(Template {
+        fields: fields
+            .iter()
+            .map(|(name, value)| {
+                parse_field(value)
+                    .map(|field| (name.to_owned(), field))
+                    .map_err(|e| format!("{}: {}", name, e))
+            })
+            .collect::<Result<Vec<(String, Field)>, String>>()?,
+    })
+}
+
+fn parse_field(value: &Value) -> Result<Field, String> {
+    match value {
+        Value::String(expression) => {
+            let mut segments = expression.split('|').collect::<Vec<&str>>();
+            let mut extraction = None;
+            let mut is_list = false;
+            // A css selector may contain a pipe (e.g. [lang|=en]), so only known modes are read from the end
+            while segments.len() > 1 {
+                let mode = segments.last().unwrap().trim();
+                match mode {
+                    "list" => is_list = true,
+                    "text" => extraction = extraction.or(Some(Extraction::Text)),
+                    "html" => extraction = extraction.or(Some(Extraction::Html)),
+                    "count" => extraction = extraction.or(Some(Extraction::Count)),
+                    _ => match mode.strip_prefix("attr:") {
+                        Some(name) if !name.trim().is_empty() => {
+                            extraction =
+                                extraction.or(Some(Extraction::Attribute(name.trim().to_string())))
+                        }
+                        _ => break,
+                    },
+                }
+                segments.pop();
+            }
+
+            Ok(Field::Value {
+                selectors: parse_selectors(segments.join("|").as_str())?,
+                extraction: extraction.unwrap_or(Extraction::Text),
+                is_list,
+            })
+        }
+        Value::Object(object) => {
+            let selector = object
+                .get("selector")
+                .and_then(|selector| selector.as_str())
+                .ok_or_else(|| "a selector property is expected".to_string())?;
+            let template = object
+                .get("each")
+                .ok_or_else(|| "an each property is expected".to_string())?;
+
+            Ok(Field::Each {
+                selectors: parse_selectors(selector)?,
+                template: parse_template_value(template)?,
+            })
+        }
+        _ => Err("a field must be a string or an object".to_string()),
+    }
+}
+
+fn parse_selectors(selector: &str) -> Result<Vec<CssSelector>, String> {
+    match selector.trim() {
+        "" => Err("a selector is expected".to_string()),
+        selector => Ok(parser::parse(selector.to_string())),
+    }
+}
+
+// The settings rendering the extracted markup and texts, built once for the whole document
+struct ExtractSettings {
+    html: SerializeSettings,
+    text: SerializeSettings,
+}
+
+// Extracts a JSON object from the descendants of the node, the settings are used to render texts,
+// attributes and markup (e.g. with the base url of the document)
+pub fn extract(
+    template: &Template,
+    node: &Handle,
+    settings_builder: &SerializeSettingsBuilder,
+) -> io::Result<Value> {
+    let mut text_settings_builder = settings_builder.clone();
+    text_settings_builder.should_render_text_only();
+    let settings = ExtractSettings {
+        html: settings_builder.clone().build(),
+        text: text_settings_builder.build(),
+    };
+    extract_record(template, node, &settings)
+}
+
+fn extract_record(
+    template: &Template,
+    node: &Handle,
+    settings: &ExtractSettings,
+) -> io::Result<Value> {
+    let mut object = Map::new();
+
+    for (name, field) in template.fields.iter() {
+        let value = match field {
+            Field::Value {
+                selectors,
+                extraction,
+                is_list,
+            } => {
+                let nodes = filter::filter_descendants(node, selectors);
+                match extraction {
+                    Extraction::Count => Value::from(nodes.len()),
+                    _ if *is_list => Value::Array(
+                        nodes
+                            .iter()
+                            .map(|node| extract_value(extraction, node, settings))
+                            .collect::<io::Result<Vec<Value>>>()?,
+                    ),
+                    _ => match nodes.first() {
+                        Some(node) => extract_value(extraction, node, settings)?,
+                        None => Value::Null,
+                    },
+                }
+            }
+            Field::Each {
+                selectors,
+                template,
+            } => Value::Array(
+                filter::filter_descendants(node, selectors)
+                    .iter()
+                    .map(|node| extract_record(template, node, settings))
+                    .collect::<io::Result<Vec<Value>>>()?,
+            ),
+        };
+        object.insert(name.to_owned(), value);
+    }
+
+    Ok(Value::Object(object))
+}
+
+fn extract_value(
+    extraction: &Extraction,
+    node: &Handle,
+    settings: &ExtractSettings,
+) -> io::Result<Value> {
+    let settings = match extraction {
+        Extraction::Text => &settings.text,
+        Extraction::Attribute(name) => {
+            return Ok(renderer::serialize_attribute(&settings.html, node, name)
+                .map_or(Value::Null, Value::String))
+        }
+        Extraction::Html => &settings.html,
+        Extraction::Count => return Ok(Value::from(1)),
+    };
+
+    renderer::serialize_node(settings, node).map(|value| Value::String(value.unwrap_or_default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use serde_json::Value;
+    use std::{env, fs};
+    use url::Url;
+
+    use super::*;
+    use crate::filter::parse_html;
+
+    #[test]
+    fn extract_records() {
+        let scenarios = vec![
+            (
+                // Extract texts, attributes, markup, counts and lists
+                "product_page",
+                SerializeSettingsBuilder::new(),
+            ),
+            (
+                // Extract records scoped to every matched node and resolve urls
+                "product_list",
+                {
+                    let mut s = SerializeSettingsBuilder::new();
+                    s.set_base_url(Url::parse("https://shop.example.com/catalog/").unwrap());
+                    s
+                },
+            ),
+        ];
+
+        let read = |filename: String| {
+            fs::read_to_string(
+                env::var("CARGO_MANIFEST_DIR").unwrap() + "/src/template/" + &filename,
+            )
+            .unwrap()
+        };
+
+        for (name, settings) in scenarios {
+            let template = parse_template(read(name.to_string() + ".json").as_str()).unwrap();
+            let document = parse_html(read(name.to_string() + ".html").as_bytes());
+            let expected: Value =
+                serde_json::from_str(read(name.to_string() + "_expected.json").as_str()).unwrap();
+
+            assert_eq!(extract(&template, &document, &settings).unwrap(), expected);
+        }
+
+        // The fields are extracted in the order of the template
+        let template =
+            parse_template(r#"{"title": "h1", "count": "p | count", "body": "p"}"#).unwrap();
+        let record = extract(
+            &template,
+            &parse_html(b"<h1>Title</h1><p>Text</p>"),
+            &SerializeSettingsBuilder::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            record.as_object().unwrap().keys().collect::<Vec<&String>>(),
+            vec!["title", "count", "body"]
+        );
+
+        assert_eq!(
+            parse_template(r#"{"lang": "[lang|=en] | attr:lang | list"}"#).unwrap(),
+            Template {
+                fields: vec![(
+                    "lang".to_string(),
+                    Field::Value {
+                        selectors: parser::parse("[lang|=en]".to_string()),
+                        extraction: Extraction::Attribute("lang".to_string()),
+                        is_list: true,
+                    }
+                )]
+            }
+        );
+        assert_eq!(
+            parse_template(r#"{"items": {"each": {}}}"#).unwrap_err(),
+            "items: a selector property is expected"
+        );
+    }
+}
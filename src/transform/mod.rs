@@ -0,0 +1,275 @@
+use std::{cell::RefCell, collections::HashSet, rc::Rc};
+
+use html5ever::{tendril::StrTendril, Attribute, LocalName, QualName};
+use markup5ever::{namespace_url, ns};
+use markup5ever_rcdom::{Handle, Node, NodeData};
+
+use crate::filter;
+use crate::parser::{self, CssSelector};
+use crate::renderer;
+use crate::tree;
+
+// Represents a change made to every matched node
+#[derive(Clone, Debug, PartialEq)]
+pub enum Transformation {
+    // Removes the node and its descendants
+    Delete,
+    // Replaces the node with its children
+    Unwrap,
+    // Adds an attribute or replaces its value
+    SetAttribute {
+        name: String,
+        value: String,
+    },
+    // Removes the attributes matching a name or a glob (e.g. on*)
+    RemoveAttribute(String),
+    // Replaces the children of the node with a text
+    ReplaceText(String),
+    // Moves the node into a new element put in its place
+    Wrap {
+        name: QualName,
+        attrs: Vec<Attribute>,
+    },
+}
+
+// A transformation applied to the nodes matching any of the comma separated selectors
+#[derive(Clone, Debug)]
+pub struct Operation {
+    selector_groups: Vec<Vec<CssSelector>>,
+    transformation: Transformation,
+}
+
+impl Operation {
+    pub fn new(selectors: &str, transformation: Transformation) -> Operation {
+        Operation {
+            selector_groups: parser::parse_groups(selectors),
+            transformation,
+        }
+    }
+}
+
+// The attribute is given as NAME=VALUE, an attribute given without value is set empty.
+// The name can't be empty nor contain characters ending it in a start tag (whitespaces, quotes, >, / and =)
+pub fn parse_assignment(assignment: &str) -> Result<Transformation, String> {
+    let (name, value) = assignment.split_once('=').unwrap_or((assignment, ""));
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(format!("no attribute name found in {}", assignment));
+    }
+    if name.contains(|c: char| {
+        c.is_whitespace() || c.is_control() || matches!(c, '"' | '\'' | '>' | '/' | '=')
+    }) {
+        return Err(format!("{} is not a valid attribute name", name));
+    }
+
+    Ok(Transformation::SetAttribute {
+        name: name.to_string(),
+        value: value.to_string(),
+    })
+}
+
+// The wrapper is given as a start tag (e.g. <figure class="image">) or as an element name
+pub fn parse_wrapper(markup: &str) -> Result<Transformation, String> {
+    let markup = markup.trim();
+    let markup = if markup.starts_with('<') {
+        markup.to_string()
+    } else {
+        format!("<{}>", markup)
+    };
+
+    let fragment = filter::parse_html_fragment(markup.as_bytes(), "template");
+    let children = fragment.children.borrow();
+    children
+        .iter()
+        .find_map(|node| match &node.data {
+            NodeData::Element { name, attrs, .. } => Some(Transformation::Wrap {
+                name: name.to_owned(),
+                attrs: attrs.borrow().to_owned(),
+            }),
+            _ => None,
+        })
+        .ok_or_else(|| format!("no element found in {}", markup))
+}
+
+// Applies the operations in order, every operation sees the document changed by the previous ones
+pub fn transform(document: &Handle, operations: &[Operation]) {
+    for operation in operations {
+        // A node matched by several selectors is transformed once
+        let mut transformed = HashSet::new();
+        let nodes = operation
+            .selector_groups
+            .iter()
+            .flat_map(|selectors| filter::filter_nested(document, selectors))
+            .filter(|node| transformed.insert(Rc::as_ptr(node)))
+            .collect::<Vec<Handle>>();
+
+        for node in nodes {
+            transform_node(&node, &operation.transformation);
+        }
+    }
+}
+
+// Any node can be deleted or unwrapped, the other transformations apply to elements only
+pub fn transform_node(node: &Handle, transformation: &Transformation) {
+    let attrs = match &node.data {
+        NodeData::Element { attrs, .. } => Some(attrs),
+        _ => None,
+    };
+
+    match (transformation, attrs) {
+        (Transformation::Delete, _) => replace_node(node, vec![]),
+        (Transformation::Unwrap, _) => {
+            let children = node.children.take();
+            replace_node(node, children);
+        }
+        (Transformation::SetAttribute { name, value }, Some(attrs)) => {
+            let mut attrs = attrs.borrow_mut();
+            match attrs
+                .iter_mut()
+                .find(|attr| attr.name.local.as_ref() == name)
+            {
+                Some(attr) => attr.value = StrTendril::from(value.as_str()),
+                None => attrs.push(Attribute {
+                    name: QualName::new(None, ns!(), LocalName::from(name.as_str())),
+                    value: StrTendril::from(value.as_str()),
+                }),
+            }
+        }
+        (Transformation::RemoveAttribute(pattern), Some(attrs)) => attrs
+            .borrow_mut()
+            .retain(|attr| !renderer::is_matching_attribute(pattern, attr.name.local.as_ref())),
+        (Transformation::ReplaceText(text), Some(_)) => {
+            for child in node.children.take() {
+                child.parent.set(None);
+            }
+            tree::append(
+                node,
+                Node::new(NodeData::Text {
+                    contents: RefCell::new(StrTendril::from(text.as_str())),
+                }),
+            );
+        }
+        (Transformation::Wrap { name, attrs }, Some(_)) => {
+            let wrapper = Node::new(NodeData::Element {
+                name: name.to_owned(),
+                attrs: RefCell::new(attrs.to_owned()),
+                template_contents: None,
+                mathml_annotation_xml_integration_point: false,
+            });
+            replace_node(node, vec![wrapper.to_owned()]);
+            tree::append(&wrapper, node.to_owned());
+        }
+        _ => (),
+    }
+}
+
+// Puts the replacements in place of the node among the children of its parent, the node is detached
+fn replace_node(node: &Handle, replacements: Vec<Handle>) {
+    let parent = match tree::get_parent(node) {
+        Some(parent) => parent,
+        None => return,
+    };
+    node.parent.set(None);
+
+    let mut children = parent.children.borrow_mut();
+    if let Some(position) = children.iter().position(|child| Rc::ptr_eq(child, node)) {
+        for replacement in replacements.iter() {
+            replacement.parent.set(Some(Rc::downgrade(&parent)));
+        }
+        children.splice(position..=position, replacements);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use std::{env, fs};
+
+    use super::*;
+    use crate::filter::parse_html;
+    use crate::renderer::SerializeSettingsBuilder;
+
+    #[test]
+    fn transform_document() {
+        let scenarios = vec![
+            (
+                // Remove the nodes matching any of the selectors
+                vec![("script, .ad", Transformation::Delete)],
+                "delete_expected.html",
+            ),
+            (
+                // Unwrap nested elements
+                vec![("font", Transformation::Unwrap)],
+                "unwrap_expected.html",
+            ),
+            (
+                // Add an attribute or replace its value
+                vec![("img", parse_assignment("loading=lazy").unwrap())],
+                "set_attribute_expected.html",
+            ),
+            (
+                // Remove attributes by name or by glob
+                vec![
+                    (
+                        "[style]",
+                        Transformation::RemoveAttribute("style".to_string()),
+                    ),
+                    ("*", Transformation::RemoveAttribute("on*".to_string())),
+                ],
+                "remove_attribute_expected.html",
+            ),
+            (
+                // Replace the content of an element with a text
+                vec![("h1", Transformation::ReplaceText("<New> title".to_string()))],
+                "replace_text_expected.html",
+            ),
+            (
+                // Wrap elements then transform the wrappers
+                vec![
+                    ("img", parse_wrapper(r#"<figure class="image">"#).unwrap()),
+                    (
+                        "figure",
+                        Transformation::SetAttribute {
+                            name: "id".to_string(),
+                            value: "figure".to_string(),
+                        },
+                    ),
+                ],
+                "wrap_expected.html",
+            ),
+        ];
+
+        let directory = env::var("CARGO_MANIFEST_DIR").unwrap() + "/src/transform/";
+        let given_html = fs::read(directory.to_owned() + "page.html").unwrap();
+        for (operations, expected_filename) in scenarios {
+            let expected = fs::read_to_string(directory.to_owned() + expected_filename).unwrap();
+
+            let document = parse_html(&given_html);
+            let operations = operations
+                .into_iter()
+                .map(|(selectors, transformation)| Operation::new(selectors, transformation))
+                .collect::<Vec<Operation>>();
+            transform(&document, &operations);
+
+            let mut settings_builder = SerializeSettingsBuilder::new();
+            settings_builder.render_raw();
+            assert_eq!(
+                renderer::serialize_nodes(settings_builder, vec![document]).unwrap(),
+                expected
+            );
+        }
+
+        assert_eq!(
+            parse_assignment(" hidden ").unwrap(),
+            Transformation::SetAttribute {
+                name: "hidden".to_string(),
+                value: "".to_string(),
+            }
+        );
+        for assignment in [
+            "", "=value", " =value", "data a=1", "a\"=1", "a'", "a>b", "a/b",
+        ] {
+            assert!(parse_assignment(assignment).is_err(), "{}", assignment);
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use std::rc::Rc;
+
+use markup5ever_rcdom::Handle;
+
+// Helpers shared by the modules editing rcdom trees, a node holds a weak reference to its parent
+
+// Appends the child to the children of the parent and makes the parent its parent
+pub fn append(parent: &Handle, child: Handle) {
+    child.parent.set(Some(Rc::downgrade(parent)));
+    parent.children.borrow_mut().push(child);
+}
+
+// The parent is kept in a cell, it is taken then put back to be read
+pub fn get_parent(node: &Handle) -> Option<Handle> {
+    let parent = node.parent.take();
+    node.parent.set(parent.clone());
+    parent.and_then(|parent| parent.upgrade())
+}
+
+#[cfg(test)]
+mod tests {
+    use markup5ever_rcdom::{Node, NodeData};
+
+    use super::*;
+
+    #[test]
+    fn edit_tree() {
+        let document = Node::new(NodeData::Document);
+        let comment = Node::new(NodeData::Comment {
+            contents: "comment".into(),
+        });
+        append(&document, comment.to_owned());
+
+        assert!(get_parent(&document).is_none());
+        // The parent is still set once read
+        for _ in 0..2 {
+            assert!(get_parent(&comment).is_some_and(|parent| Rc::ptr_eq(&parent, &document)));
+        }
+        assert_eq!(document.children.borrow().len(), 1);
+    }
+}